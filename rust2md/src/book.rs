@@ -0,0 +1,867 @@
+//
+// Book mode: the chapters listed in `rust2md.toml` (or, without a
+// manifest, every crate under `src/` with a `src/main.rs`) are each
+// converted to their own markdown file, and concatenated, after the
+// book's front matter, into `index.md`. The HTML format does the same
+// with standalone pages: one per chapter, and the whole book in
+// `index.html`. The mdBook format writes a tree of its own, for
+// `mdbook build`, and the EPUB and LaTeX formats a single file each
+// (see `epub` and `latex`). The table of contents goes into `toc.md`
+// and, for other tools, `toc.json`. `[[...]]` cross-references are
+// resolved once every chapter is converted (see `xref`), and so are
+// includes of regions from other chapters (see `region`). Every
+// snippet knows the source lines it shows: the HTML links to them,
+// and, if asked, so do the markdown fences and `sourcemap.json`. With
+// `--run-tests`, each snippet also shows how its tests went (see
+// `libtest`), and examples that must not compile show the errors they
+// give (see `compile_fail`).
+//
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::cache::{self, Cache, Entry};
+use crate::compile_fail;
+use crate::diagnostics::{self, Diagnostic};
+use crate::epub;
+use crate::html::{self, Renderer};
+use crate::json;
+use crate::latex;
+use crate::libtest::{self, Outcome};
+use crate::manifest::{Format, Manifest, Metadata, Output};
+use crate::document::Document;
+use crate::markdown;
+use crate::region;
+use crate::toc::{self, Anchors, Heading};
+use crate::xref::{self, Target};
+use crate::output::{self, OutputFile};
+use crate::scaffolding::Scaffolding;
+use crate::{parse, Fences, Options, Report};
+
+// The table of contents, next to the chapters
+const TOC_MARKDOWN: &str = "toc.md";
+const TOC_JSON: &str = "toc.json";
+// Where each snippet came from, and where it ended up
+const SOURCE_MAP: &str = "sourcemap.json";
+// Where the mdBook tree goes, below the output directory
+const MDBOOK_DIR: &str = "mdbook";
+
+pub struct BookOptions {
+    pub root: PathBuf,
+    pub convert: Options,
+    pub output: Option<PathBuf>,
+    pub drafts: bool,
+    pub list: bool,
+    pub strict: bool,
+    // Compare with the files on disk instead of writing them
+    pub check: bool,
+    // Run each chapter's tests, and show how each snippet's went
+    pub run_tests: bool,
+    // The formats to write, if not the manifest's
+    pub formats: Vec<Format>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub name: String,
+    pub source: PathBuf,
+    pub title: Option<String>,
+    pub part: Option<String>,
+    pub draft: bool,
+}
+
+impl Chapter {
+    fn new(name: &str, source: PathBuf) -> Chapter {
+        Chapter { name: name.to_string(), source, title: None, part: None, draft: false }
+    }
+
+    // The directory of the chapter's crate, which `src/main.rs` is in
+    fn crate_dir<'a>(&'a self, root: &'a Path) -> &'a Path {
+        self.source.parent().and_then(Path::parent).unwrap_or(root)
+    }
+}
+
+//
+// Finds every `src/<name>/src/main.rs` below `root`, in alphabetical
+// order
+//
+pub fn discover(root: &Path) -> Result<Vec<Chapter>, String> {
+    let src = root.join("src");
+    let entries = fs::read_dir(&src)
+        .map_err(|e| format!("cannot read '{}': {}", src.display(), e))?;
+    let mut chapters = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("cannot read '{}': {}", src.display(), e))?;
+        let source = entry.path().join("src").join("main.rs");
+        if !source.is_file() {
+            continue;
+        }
+        if let Some(name) = entry.file_name().to_str() {
+            chapters.push(Chapter::new(name, source));
+        }
+    }
+    chapters.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(chapters)
+}
+
+//
+// The chapters of the book, in reading order. A manifest that lists
+// chapters is authoritative: discovered crates it neither lists nor
+// excludes are reported and left out. Otherwise every discovered
+// crate that is not excluded is a chapter, in alphabetical order.
+//
+pub fn resolve(root: &Path, manifest: Option<&Manifest>) -> Result<Vec<Chapter>, String> {
+    let discovered = discover(root)?;
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => return Ok(discovered),
+    };
+    let crate_path = |chapter: &Chapter| {
+        chapter.source.parent().and_then(Path::parent).map(Path::to_path_buf).unwrap_or_default()
+    };
+    let excluded = |chapter: &Chapter| {
+        manifest.exclude.iter().any(|path| root.join(path) == crate_path(chapter))
+    };
+    if manifest.chapters.is_empty() {
+        return Ok(discovered.into_iter().filter(|c| !excluded(c)).collect());
+    }
+
+    let mut chapters = Vec::new();
+    for entry in &manifest.chapters {
+        let source = root.join(&entry.path).join("src").join("main.rs");
+        if !source.is_file() {
+            return Err(format!("chapter '{}' is listed in the manifest but '{}' does not exist", entry.path.display(), source.display()));
+        }
+        let name = entry.path.file_name().and_then(|n| n.to_str())
+            .ok_or_else(|| format!("chapter path '{}' has no name", entry.path.display()))?;
+        chapters.push(Chapter {
+            title: entry.title.clone(),
+            part: entry.part.clone(),
+            draft: entry.draft,
+            ..Chapter::new(name, source)
+        });
+    }
+    for chapter in discovered {
+        if !excluded(&chapter) && !chapters.iter().any(|c| c.source == chapter.source) {
+            eprintln!("rust2md: skipping '{}': not listed in the manifest", crate_path(&chapter).display());
+        }
+    }
+    Ok(chapters)
+}
+
+//
+// The book's front matter: generated from the manifest's `[book]`
+// table if there is one, read from `md/header.md` otherwise
+//
+fn header(root: &Path, manifest: Option<&Manifest>) -> Result<Vec<u8>, String> {
+    if let Some(manifest) = manifest.filter(|m| !m.book.is_empty()) {
+        return Ok(manifest.book.front_matter().into_bytes());
+    }
+    let path = root.join("md").join("header.md");
+    match fs::read(&path) {
+        Ok(header) => Ok(header),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(format!("cannot read '{}': {}", path.display(), e)),
+    }
+}
+
+//
+// A chapter's display title becomes its top heading: it takes the
+// place of the chapter's first `# ` heading, or, if there is none, goes
+// before everything else
+//
+fn with_title(chapter: &Chapter, markdown: Vec<u8>) -> Vec<u8> {
+    let title = match &chapter.title {
+        Some(title) => title,
+        None => return markdown,
+    };
+    let text = String::from_utf8_lossy(&markdown);
+    let mut in_code = false;
+    let mut start = 0;
+    for line in text.split_inclusive('\n') {
+        if line.starts_with("```") {
+            in_code = !in_code;
+        } else if !in_code && line.starts_with("# ") {
+            let newline = if line.ends_with('\n') { "\n" } else { "" };
+            return format!("{}# {}{}{}", &text[..start], title, newline, &text[start + line.len()..]).into_bytes();
+        }
+        start += line.len();
+    }
+    let mut titled = format!("\n# {}\n", title).into_bytes();
+    titled.extend(markdown);
+    titled
+}
+
+// A chapter's source file, as a path from the root of the book
+fn source_path(root: &Path, source: &Path) -> String {
+    let path = source.strip_prefix(root).unwrap_or(source);
+    let parts: Vec<String> = path.components().map(|c| c.as_os_str().to_string_lossy().into_owned()).collect();
+    parts.join("/")
+}
+
+//
+// Where the tests that failed are defined, or the start of their
+// snippet, with a word about it, when the definition cannot be found
+//
+fn failed_tests(document: &Document) -> Vec<Diagnostic> {
+    let mut failed = Vec::new();
+    for snippet in document.snippets() {
+        for (name, outcome) in &snippet.tests {
+            if *outcome == Outcome::Failed {
+                let diagnostic = match snippet.lines.iter().position(|line| defines(line, name)) {
+                    Some(offset) => Diagnostic::new(snippet.first_line + offset, format!("test {} failed", name)),
+                    None => Diagnostic::new(snippet.first_line, format!("test {} failed, but where it is defined was not found", name)),
+                };
+                failed.push(diagnostic);
+            }
+        }
+    }
+    failed
+}
+
+// Whether a line defines the function `name`, not one named `name_x`
+fn defines(line: &str, name: &str) -> bool {
+    let signature = format!("fn {}", name);
+    line.match_indices(&signature).any(|(at, _)| {
+        let before = line[..at].chars().next_back();
+        let after = line[at + signature.len()..].trim_start().chars().next();
+        !before.is_some_and(|c| c.is_alphanumeric() || c == '_') && matches!(after, Some('(') | Some('<'))
+    })
+}
+
+fn count_lines(text: &[u8]) -> usize {
+    text.iter().filter(|&&b| b == b'\n').count()
+}
+
+fn print_plan(chapters: &[Chapter]) {
+    for chapter in chapters {
+        let mut line = format!("{:<16} {}", chapter.name, chapter.source.display());
+        if let Some(title) = &chapter.title {
+            line.push_str(&format!("  \"{}\"", title));
+        }
+        if let Some(part) = &chapter.part {
+            line.push_str(&format!("  [part: {}]", part));
+        }
+        if chapter.draft {
+            line.push_str("  (draft)");
+        }
+        println!("{}", line);
+    }
+}
+
+pub fn build(options: &BookOptions) -> Result<(), String> {
+    build_cached(options, &mut Cache::default()).map(|_| ())
+}
+
+//
+// Builds the book, reusing what `cache` kept of the chapters that have
+// not changed since the last build, and returns the files written
+//
+pub fn build_cached(options: &BookOptions, cache: &mut Cache) -> Result<Vec<PathBuf>, String> {
+    let manifest = Manifest::load(&options.root)?;
+    let chapters = resolve(&options.root, manifest.as_ref())?;
+    if options.list {
+        print_plan(&chapters);
+        return Ok(Vec::new());
+    }
+
+    let (files, problems, failures) = render(options, manifest.as_ref(), chapters, cache)?;
+    let mut written = Vec::new();
+    if options.check {
+        let stale = output::check_all(&files);
+        if !stale.is_empty() {
+            return Err(output::stale_failure(&stale, "rust2md book"));
+        }
+    } else {
+        written = output::write_all(&files)?;
+    }
+    if failures > 0 {
+        return Err(format!("{} failing test{}", failures, if failures == 1 { "" } else { "s" }));
+    }
+    if options.strict && problems > 0 {
+        return Err(diagnostics::strict_failure(problems));
+    }
+    Ok(written)
+}
+
+// Where the book is written
+pub fn output_dir(options: &BookOptions, manifest: Option<&Manifest>) -> PathBuf {
+    match &options.output {
+        Some(output) => output.clone(),
+        None => options.root.join(manifest.map(|m| m.output.dir.clone()).unwrap_or_else(|| Output::default().dir)),
+    }
+}
+
+//
+// Every file of the book, in every output format, along with how many
+// diagnostics were printed on the way, and how many tests failed
+//
+pub fn render(options: &BookOptions, manifest: Option<&Manifest>, chapters: Vec<Chapter>, cache: &mut Cache) -> Result<(Vec<OutputFile>, usize, usize), String> {
+    let mut targets = manifest.map(|m| m.output.clone()).unwrap_or_default();
+    targets.dir = output_dir(options, manifest);
+    if !options.formats.is_empty() {
+        targets.formats = options.formats.clone();
+    }
+    let header = header(&options.root, manifest)?;
+    let metadata = match manifest.filter(|m| !m.book.is_empty()) {
+        Some(manifest) => manifest.book.clone(),
+        None => Metadata::from_front_matter(&String::from_utf8_lossy(&header)),
+    };
+
+    let mut parsed = Vec::new();
+    let mut documents: Vec<Document> = Vec::new();
+    let mut problems = 0;
+    let mut failures = 0;
+    for chapter in chapters {
+        let text = fs::read(&chapter.source).map_err(|e| format!("cannot read '{}': {}", chapter.source.display(), e))?;
+        // Testing a chapter reads the whole of its crate
+        let hash = if options.run_tests { cache::hash_files(chapter.crate_dir(&options.root))? } else { cache::hash(&text) };
+        let (document, failed) = match cache.get(&chapter.source, hash, options.run_tests) {
+            Some(entry) => (entry.document.clone(), entry.failures.clone()),
+            None => {
+                let (document, failed) = build_chapter(options, &chapter, &text)?;
+                let entry = Entry { hash, tested: options.run_tests, document: document.clone(), failures: failed.clone() };
+                cache.insert(&chapter.source, entry);
+                (document, failed)
+            }
+        };
+        let report = Report::new(&document);
+        problems += diagnostics::print(&chapter.source.display().to_string(), &report.diagnostics, options.strict);
+        //
+        // Crates without a single comment (such as `game`) are not
+        // chapters, just code living next to them
+        //
+        if report.is_empty() {
+            eprintln!("rust2md: skipping '{}': no comments found", chapter.source.display());
+            continue;
+        }
+        // A failing test is an error, strict or not
+        failures += diagnostics::print(&chapter.source.display().to_string(), &failed, true);
+        parsed.push((chapter, report));
+        documents.push(document);
+    }
+
+    //
+    // Regions can only be included once every chapter is parsed, as
+    // they may come from any of them
+    //
+    let sources: Vec<String> = parsed.iter().map(|(chapter, _)| source_path(&options.root, &chapter.source)).collect();
+    let names: Vec<&str> = parsed.iter().map(|(chapter, _)| chapter.name.as_str()).collect();
+    let files: Vec<Option<&str>> = sources.iter().map(|source| Some(source.as_str())).collect();
+    let includes = region::include(&mut documents, &names, &files);
+    for ((chapter, _), unresolved) in parsed.iter().zip(&includes) {
+        problems += diagnostics::print(&chapter.source.display().to_string(), unresolved, options.strict);
+    }
+
+    let mut converted = Vec::new();
+    for (((chapter, report), document), source) in parsed.into_iter().zip(documents).zip(sources) {
+        //
+        // The HTML always needs to know where snippets came from, to
+        // link to them; the markdown only says so if asked to
+        //
+        let annotated = Options { source_lines: true, source: Some(source.clone()), ..options.convert.clone() };
+        let plain = Options { source_lines: options.convert.source_lines || targets.source_lines, ..annotated.clone() };
+        let write = |options: &Options| {
+            let mut markdown = Vec::new();
+            document
+                .write_markdown(&mut markdown, options)
+                .map_err(|e| format!("cannot convert '{}': {}", chapter.source.display(), e))?;
+            Ok::<_, String>(markdown)
+        };
+        let untitled = write(&plain)?;
+        let lines = count_lines(&untitled);
+        let markdown = with_title(&chapter, untitled);
+        let annotated = with_title(&chapter, write(&annotated)?);
+        // Cross-references find items in the tests, scaffolding or not
+        let whole = with_title(&chapter, write(&Options { test_scaffolding: Scaffolding::Keep, ..plain.clone() })?);
+        let mdbook = with_title(&chapter, write(&Options { fences: Fences::MdBook, ..plain.clone() })?);
+        // The title, if added, pushes every fence down
+        let offset = count_lines(&markdown) - lines;
+        let snippets = document
+            .snippets()
+            .zip(document.fence_lines(&plain))
+            .map(|(snippet, fence)| {
+                let (first_line, last_line) = snippet.shown_lines(&plain);
+                Snippet { file: snippet.file.clone().unwrap_or_else(|| source.clone()), first_line, last_line, fence: fence + offset }
+            })
+            .collect();
+        converted.push((chapter, markdown, annotated, whole, mdbook, snippets, report.references));
+    }
+
+    //
+    // Anchors are handed out across the whole book, drafts included, so
+    // that every format links to the same ones
+    //
+    let mut anchors = Anchors::default();
+    let mut book = Book { chapters: Vec::new(), index: xref::Index::default(), drafts: options.drafts };
+    let mut references = Vec::new();
+    for (chapter, markdown, annotated, whole, mdbook, snippets, chapter_references) in converted {
+        let blocks = markdown::parse(&String::from_utf8_lossy(&whole));
+        let headings = toc::headings(&blocks, &chapter.name, &mut anchors);
+        book.index.add_chapter(&chapter.name, &blocks, &headings);
+        references.push(chapter_references);
+        book.chapters.push(Rendered { chapter, markdown, annotated, mdbook, headings, snippets });
+    }
+    for (rendered, references) in book.chapters.iter().zip(references) {
+        let unresolved: Vec<Diagnostic> = references
+            .iter()
+            .filter_map(|r| book.index.resolve(&r.target, &rendered.chapter.name).err().map(|e| (r, e)))
+            .map(|(r, e)| Diagnostic::new(r.line, format!("unresolved reference [[{}]]: {}", r.target, e)))
+            .collect();
+        problems += diagnostics::print(&rendered.chapter.source.display().to_string(), &unresolved, options.strict);
+    }
+
+    let mut files = Vec::new();
+    for format in &targets.formats {
+        match format {
+            Format::Markdown => files.extend(markdown_files(&targets, &header, &book)),
+            Format::Html => files.extend(html_files(&targets, &metadata, &book, &options.root)),
+            Format::MdBook => files.extend(mdbook_files(&targets, &metadata, &book)),
+            Format::Epub => files.push(epub_file(&targets, &metadata, &book)),
+            Format::Latex => files.push(latex_file(&targets, &metadata, &book)),
+        }
+    }
+    if targets.source_map {
+        files.push(OutputFile::new(targets.dir.join(SOURCE_MAP), source_map(&targets, &header, &book)));
+    }
+    let chapters: Vec<(&str, bool, &[Heading])> = book
+        .chapters
+        .iter()
+        .map(|r| (r.chapter.name.as_str(), r.chapter.draft, r.headings.as_slice()))
+        .collect();
+    let contents = toc::json(metadata.title.as_deref(), &chapters);
+    files.push(OutputFile::new(targets.dir.join(TOC_JSON), contents));
+    Ok((files, problems, failures))
+}
+
+//
+// A chapter parsed from `text`, and, with `--run-tests`, tested, with
+// the tests and compile-fail examples that failed. The tests are those
+// of the chapter's own crate, so they are run before any region is
+// included from another.
+//
+fn build_chapter(options: &BookOptions, chapter: &Chapter, text: &[u8]) -> Result<(Document, Vec<Diagnostic>), String> {
+    let mut document = parse(text, &options.convert).map_err(|e| format!("cannot convert '{}': {}", chapter.source.display(), e))?;
+    let mut failed = Vec::new();
+    if options.run_tests && !Report::new(&document).is_empty() {
+        libtest::stamp(&mut document, &libtest::run(chapter.crate_dir(&options.root))?);
+        failed = failed_tests(&document);
+        failed.extend(compile_fail::check(&mut document)?);
+        failed.sort_by_key(|diagnostic| diagnostic.line);
+    }
+    Ok((document, failed))
+}
+
+//
+// A chapter converted to markdown, with the anchors of its headings
+//
+struct Rendered {
+    chapter: Chapter,
+    markdown: Vec<u8>,
+    // The markdown with every fence saying where its code came from
+    annotated: Vec<u8>,
+    // The markdown with fences as mdBook wants them
+    mdbook: Vec<u8>,
+    headings: Vec<Heading>,
+    snippets: Vec<Snippet>,
+}
+
+// Where a snippet's code came from, and where it went
+struct Snippet {
+    file: String,
+    first_line: usize,
+    last_line: usize,
+    // The line of the chapter's markdown the snippet's fence is on
+    fence: usize,
+}
+
+struct Book {
+    chapters: Vec<Rendered>,
+    // What cross-references can lead to
+    index: xref::Index,
+    drafts: bool,
+}
+
+impl Book {
+    // Drafts get their own page, but stay out of the book until they
+    // are ready (or `--drafts` is given)
+    fn in_book(&self, chapter: &Chapter) -> bool {
+        !chapter.draft || self.drafts
+    }
+
+    // The headings of the chapters in the book, down to `toc::DEPTH`
+    fn headings(&self) -> Vec<Heading> {
+        let chapters = self.chapters.iter().filter(|r| self.in_book(&r.chapter));
+        chapters.flat_map(|r| r.headings.iter().filter(|h| h.level <= toc::DEPTH).cloned()).collect()
+    }
+
+    //
+    // A chapter's markdown (or `markdown`, the same with its
+    // fences annotated) with its cross-references made into links:
+    // to an anchor on the same page when the target is on it, and to
+    // the target chapter's own `.extension` file otherwise. The page
+    // holds the whole book if `whole_book`, or just the chapter.
+    //
+    fn linked(&self, rendered: &Rendered, markdown: &[u8], extension: &str, whole_book: bool) -> String {
+        let link = |target: &Target| {
+            let on_page = if whole_book {
+                self.chapters.iter().any(|r| r.chapter.name == target.chapter && self.in_book(&r.chapter))
+            } else {
+                target.chapter == rendered.chapter.name
+            };
+            if on_page {
+                format!("#{}", target.anchor)
+            } else {
+                format!("{}.{}#{}", target.chapter, extension, target.anchor)
+            }
+        };
+        xref::rewrite(&String::from_utf8_lossy(markdown), &self.index, &rendered.chapter.name, &link)
+    }
+}
+
+fn markdown_files(targets: &Output, header: &[u8], book: &Book) -> Vec<OutputFile> {
+    let mut files = Vec::new();
+    let mut index = header.to_vec();
+    for rendered in &book.chapters {
+        let markdown = book.linked(rendered, &rendered.markdown, "md", false);
+        files.push(OutputFile::new(targets.dir.join(format!("{}.md", rendered.chapter.name)), markdown));
+        if book.in_book(&rendered.chapter) {
+            index.extend_from_slice(book.linked(rendered, &rendered.markdown, "md", true).as_bytes());
+        }
+    }
+    files.push(OutputFile::new(targets.dir.join(&targets.index), index));
+
+    let headings = book.headings();
+    let mut contents = String::from("# Contents\n\n");
+    contents.push_str(&toc::markdown(&toc::shallow(&headings, toc::DEPTH), &|h| format!("{}.md#{}", h.chapter, h.anchor)));
+    files.push(OutputFile::new(targets.dir.join(TOC_MARKDOWN), contents));
+    files
+}
+
+//
+// Every chapter gets a page of its own, with its table of contents and
+// links to the chapters on either side. The index page holds the whole
+// book, as `pandoc --standalone --toc` used to make it.
+//
+fn html_files(targets: &Output, metadata: &Metadata, book: &Book, root: &Path) -> Vec<OutputFile> {
+    let index = Path::new(&targets.index).with_extension("html").to_string_lossy().into_owned();
+    let page_name = |chapter: &Chapter| format!("{}.html", chapter.name);
+    let same_page = |heading: &Heading| format!("#{}", heading.anchor);
+    let source_root = source_root(targets, root);
+    let source_link = |file: &str, start: usize, end: usize| match (&targets.source_url, &source_root) {
+        (Some(url), _) => Some(format!("{}{}#L{}-L{}", url, file, start, end)),
+        (None, Some(root)) => Some(format!("{}{}", root, file)),
+        (None, None) => None,
+    };
+    let body = |rendered: &Rendered, whole_book: bool| {
+        let blocks = markdown::parse(&book.linked(rendered, &rendered.annotated, "html", whole_book));
+        Renderer::new(&rendered.headings).with_source_links(&source_link).blocks(&blocks)
+    };
+    let chapters = &book.chapters;
+    let mut files = Vec::new();
+    let mut book_body = String::new();
+    for (n, rendered) in chapters.iter().enumerate() {
+        let chapter = &rendered.chapter;
+        let title = rendered.headings.first().map_or_else(|| chapter.name.clone(), |h| h.text.clone());
+
+        let mut nav = String::from("<nav class=\"pages\">\n");
+        if let Some(previous) = n.checked_sub(1).map(|p| &chapters[p].chapter) {
+            nav.push_str(&format!("<a href=\"{}\" rel=\"prev\">&larr; Previous</a>\n", page_name(previous)));
+        }
+        nav.push_str(&format!("<a href=\"{}\">Contents</a>\n", index));
+        if let Some(next) = chapters.get(n + 1).map(|r| &r.chapter) {
+            nav.push_str(&format!("<a href=\"{}\" rel=\"next\">Next &rarr;</a>\n", page_name(next)));
+        }
+        nav.push_str("</nav>\n");
+        nav.push_str(&html::toc(&toc::shallow(&rendered.headings, toc::DEPTH), &same_page));
+        let page = html::page(&title, &Metadata::default(), targets.theme, &nav, &body(rendered, false));
+        files.push(OutputFile::new(targets.dir.join(page_name(chapter)), page));
+
+        if book.in_book(chapter) {
+            book_body.push_str(&body(rendered, true));
+        }
+    }
+    let title = metadata.title.clone().unwrap_or_else(|| String::from("index"));
+    let toc = html::toc(&toc::shallow(&book.headings(), toc::DEPTH), &same_page);
+    let page = html::page(&title, metadata, targets.theme, &toc, &book_body);
+    files.push(OutputFile::new(targets.dir.join(index), page));
+    files
+}
+
+//
+// An mdBook source tree: `book.toml`, and, in `src/`, a file for each
+// chapter and `SUMMARY.md` listing them in order, under their parts,
+// with their headings nested below them as links to anchors. Drafts
+// are listed as mdBook drafts, without a file to link to, until they
+// are in the book.
+//
+fn mdbook_files(targets: &Output, metadata: &Metadata, book: &Book) -> Vec<OutputFile> {
+    let dir = targets.dir.join(MDBOOK_DIR);
+    let mut config = String::from("[book]\n");
+    if let Some(title) = &metadata.title {
+        config.push_str(&format!("title = {}\n", json::string(title)));
+    }
+    if let Some(author) = &metadata.author {
+        config.push_str(&format!("authors = [{}]\n", json::string(author)));
+    }
+    config.push_str("src = \"src\"\n");
+    let mut files = vec![OutputFile::new(dir.join("book.toml"), config)];
+
+    let mut summary = String::from("# Summary\n\n");
+    let mut part = None;
+    for rendered in &book.chapters {
+        let chapter = &rendered.chapter;
+        let file = format!("{}.md", chapter.name);
+        if chapter.part.is_some() && chapter.part != part {
+            part = chapter.part.clone();
+            summary.push_str(&format!("\n# {}\n\n", part.as_deref().unwrap_or_default()));
+        }
+        let title = rendered.headings.first().map_or_else(|| chapter.name.clone(), |h| h.text.clone());
+        let title = title.replace('[', "\\[").replace(']', "\\]");
+        let link = if book.in_book(chapter) { file.as_str() } else { "" };
+        summary.push_str(&format!("- [{}]({})\n", title, link));
+        // The chapter's own headings nest below it, down to the depth of
+        // the table of contents
+        let entries = toc::shallow(rendered.headings.get(1..).unwrap_or_default(), toc::DEPTH);
+        let anchor = |heading: &Heading| if link.is_empty() { String::new() } else { format!("{}#{}", link, heading.anchor) };
+        for line in toc::markdown(&entries, &anchor).lines() {
+            summary.push_str(&format!("  {}\n", line));
+        }
+        files.push(OutputFile::new(dir.join("src").join(&file), book.linked(rendered, &rendered.mdbook, "md", false)));
+    }
+    files.push(OutputFile::new(dir.join("src").join("SUMMARY.md"), summary));
+    files
+}
+
+//
+// The chapters in the book, as an EPUB named after it. Their links go
+// from one XHTML page to another.
+//
+fn epub_file(targets: &Output, metadata: &Metadata, book: &Book) -> OutputFile {
+    let chapters: Vec<epub::Chapter> = book
+        .chapters
+        .iter()
+        .filter(|rendered| book.in_book(&rendered.chapter))
+        .map(|rendered| {
+            let blocks = markdown::parse(&book.linked(rendered, &rendered.markdown, "xhtml", false));
+            epub::Chapter {
+                name: &rendered.chapter.name,
+                title: rendered.headings.first().map_or_else(|| rendered.chapter.name.clone(), |h| h.text.clone()),
+                body: Renderer::new(&rendered.headings).xhtml().blocks(&blocks),
+                headings: &rendered.headings,
+            }
+        })
+        .collect();
+    OutputFile::new(targets.dir.join(format!("{}.epub", file_stem(metadata))), epub::package(metadata, &chapters))
+}
+
+//
+// The chapters in the book, as one LaTeX document named after it. Links
+// between chapters become links within the document.
+//
+fn latex_file(targets: &Output, metadata: &Metadata, book: &Book) -> OutputFile {
+    let mut body = String::new();
+    for rendered in book.chapters.iter().filter(|rendered| book.in_book(&rendered.chapter)) {
+        let blocks = markdown::parse(&book.linked(rendered, &rendered.markdown, "tex", true));
+        let mut renderer = latex::Renderer::new(&rendered.headings);
+        body.push_str(&renderer.blocks(&blocks));
+        for html in renderer.dropped() {
+            eprintln!("rust2md: {}: HTML block left out of the LaTeX output: {}", rendered.chapter.source.display(), html);
+        }
+    }
+    OutputFile::new(targets.dir.join(format!("{}.tex", file_stem(metadata))), latex::document(metadata, &body))
+}
+
+// The name of a file that holds the whole book, after its title
+fn file_stem(metadata: &Metadata) -> String {
+    metadata.title.as_deref().map(toc::slug).filter(|slug| !slug.is_empty()).unwrap_or_else(|| String::from("book"))
+}
+
+//
+// The root of the book, as a path from the output directory, for
+// "edit source" links to use when there is no `source_url`. There is
+// none when the output is not inside the book.
+//
+fn source_root(targets: &Output, root: &Path) -> Option<String> {
+    let dir = targets.dir.strip_prefix(root).ok()?;
+    Some("../".repeat(dir.components().count()))
+}
+
+//
+// Every snippet of the book: its source file and lines, and the line
+// of its fence in the chapter's markdown file and in the index
+//
+fn source_map(targets: &Output, header: &[u8], book: &Book) -> String {
+    let mut out = String::from("{\n  \"snippets\": [");
+    let mut index_line = count_lines(header);
+    let mut first = true;
+    for rendered in &book.chapters {
+        let in_book = book.in_book(&rendered.chapter);
+        for snippet in &rendered.snippets {
+            out.push_str(if first { "\n" } else { ",\n" });
+            first = false;
+            out.push_str(&format!(
+                "    {{\"chapter\": {}, \"file\": {}, \"first_line\": {}, \"last_line\": {}, \"markdown\": {{{}: {}",
+                json::string(&rendered.chapter.name),
+                json::string(&snippet.file),
+                snippet.first_line,
+                snippet.last_line,
+                json::string(&format!("{}.md", rendered.chapter.name)),
+                snippet.fence
+            ));
+            if in_book {
+                out.push_str(&format!(", {}: {}", json::string(&targets.index), index_line + snippet.fence));
+            }
+            out.push_str("}}");
+        }
+        if in_book {
+            index_line += count_lines(&rendered.markdown);
+        }
+    }
+    out.push_str(if first { "]\n}\n" } else { "\n  ]\n}\n" });
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    // A book in a directory of its own, with `files` in it
+    fn book(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rust2md-book-{}-{}", name, process::id()));
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    fn options(root: &Path) -> BookOptions {
+        BookOptions {
+            root: root.to_path_buf(),
+            convert: Options::default(),
+            output: None,
+            drafts: false,
+            list: false,
+            strict: false,
+            check: false,
+            run_tests: false,
+            formats: Vec::new(),
+        }
+    }
+
+    fn names(chapters: &[Chapter]) -> Vec<&str> {
+        chapters.iter().map(|chapter| chapter.name.as_str()).collect()
+    }
+
+    // The files of the book built from `root`, by their path from the output directory
+    fn files(options: &BookOptions, manifest: &str) -> Vec<(String, String)> {
+        let manifest = Manifest::parse(manifest).unwrap();
+        let chapters = resolve(&options.root, Some(&manifest)).unwrap();
+        let (files, _, _) = render(options, Some(&manifest), chapters, &mut Cache::default()).unwrap();
+        let dir = output_dir(options, Some(&manifest));
+        files
+            .into_iter()
+            .map(|file| (file.path.strip_prefix(&dir).unwrap().display().to_string(), String::from_utf8(file.contents).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_chapters_are_resolved() {
+        let root = book(
+            "resolve",
+            &[("src/b/src/main.rs", "// B\n"), ("src/a/src/main.rs", "// A\n"), ("src/game/src/main.rs", "fn main() {}\n"), ("src/notes/x.md", "")],
+        );
+        let resolved = |manifest: &str| resolve(&root, Some(&Manifest::parse(manifest).unwrap())).map(|chapters| names(&chapters).join(" "));
+        let discovered = discover(&root).map(|chapters| names(&chapters).join(" "));
+        let unlisted = resolve(&root, None).map(|chapters| names(&chapters).join(" "));
+        let excluded = resolved("exclude = [\"src/game\"]\n");
+        let listed = resolved("exclude = [\"src/game\"]\n[[chapter]]\npath = \"src/b\"\ntitle = \"Bee\"\n[[chapter]]\npath = \"src/a\"\n");
+        // `a` is neither listed nor excluded, so it is reported and left out
+        let partial = resolved("exclude = [\"src/game\"]\n[[chapter]]\npath = \"src/b\"\n");
+        let missing = resolved("[[chapter]]\npath = \"src/c\"\n");
+        fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(discovered.as_deref(), Ok("a b game"));
+        assert_eq!(unlisted.as_deref(), Ok("a b game"));
+        assert_eq!(excluded.as_deref(), Ok("a b"));
+        assert_eq!(listed.as_deref(), Ok("b a"));
+        assert_eq!(partial.as_deref(), Ok("b"));
+        assert!(missing.unwrap_err().starts_with("chapter 'src/c' is listed in the manifest but"));
+    }
+
+    #[test]
+    fn test_titles() {
+        let chapter = Chapter { title: Some(String::from("Borrowing")), ..Chapter::new("b", PathBuf::from("b.rs")) };
+        let titled = |markdown: &str| String::from_utf8(with_title(&chapter, markdown.as_bytes().to_vec())).unwrap();
+        // The title takes the place of the first top heading, outside code
+        assert_eq!(titled("\nIntro\n\n``` sh\n# not a heading\n```\n\n# Ownership\n\n# Moves\n"), "\nIntro\n\n``` sh\n# not a heading\n```\n\n# Borrowing\n\n# Moves\n");
+        // Or comes first, when there is none
+        assert_eq!(titled("\n## Moves\n"), "\n# Borrowing\n\n## Moves\n");
+        let untitled = Chapter::new("b", PathBuf::from("b.rs"));
+        assert_eq!(with_title(&untitled, b"\n## Moves\n".to_vec()), b"\n## Moves\n");
+    }
+
+    #[test]
+    fn test_drafts_stay_out_of_the_book() {
+        let root = book("drafts", &[("src/a/src/main.rs", "// # A\n"), ("src/b/src/main.rs", "// # B\n")]);
+        let manifest = "[book]\ntitle = \"T\"\n[[chapter]]\npath = \"src/a\"\ndraft = true\n[[chapter]]\npath = \"src/b\"\n";
+        let without = files(&options(&root), manifest);
+        let with = files(&BookOptions { drafts: true, ..options(&root) }, manifest);
+        fs::remove_dir_all(&root).unwrap();
+
+        let file = |files: &[(String, String)], name: &str| files.iter().find(|(path, _)| path == name).map(|(_, contents)| contents.clone());
+        let names: Vec<&str> = without.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(names, ["a.md", "b.md", "index.md", "toc.md", "toc.json"]);
+        // Drafts get their own file, but only come into the index with --drafts
+        assert_eq!(file(&without, "index.md").unwrap(), "---\ntitle: T\n---\n\n\n# B\n");
+        assert_eq!(file(&with, "index.md").unwrap(), "---\ntitle: T\n---\n\n\n# A\n# B\n");
+        assert_eq!(file(&without, "toc.md").unwrap(), "# Contents\n\n- [B](b.md#b)\n");
+    }
+
+    #[test]
+    fn test_failed_tests() {
+        let mut document = parse("// Text\n#[test]\nfn test_ab() {}\n#[test]\nfn test_a() {\n    panic!();\n}\n".as_bytes(), &Options::default()).unwrap();
+        let Some(crate::document::Block::Code(snippet)) = document.blocks.get_mut(1) else { panic!("no snippet") };
+        snippet.tests = vec![(String::from("test_a"), Outcome::Failed), (String::from("gone"), Outcome::Failed), (String::from("test_ab"), Outcome::Passed)];
+        assert_eq!(
+            failed_tests(&document),
+            [Diagnostic::new(5, "test test_a failed"), Diagnostic::new(2, "test gone failed, but where it is defined was not found")]
+        );
+        assert!(defines("    pub fn test_x<T>() {", "test_x"));
+        assert!(!defines("fn test_xy() {", "test_x"));
+        assert!(!defines("fn my_test_x() {", "test_x"));
+    }
+
+    #[test]
+    fn test_mdbook() {
+        let root = book(
+            "mdbook",
+            &[
+                ("src/a/src/main.rs", "// # A\n//\n// ## Moves\n"),
+                ("src/b/src/main.rs", "// # B\n//\n// ## Slices [and] arrays\n//\n// ### Ranges\n//\n// #### Too deep\n//\n// ## Strings\n"),
+            ],
+        );
+        let manifest = "[book]\ntitle = \"T\"\nauthor = \"Me\"\n[output]\nformats = [\"mdbook\"]\n\
+                        [[chapter]]\npath = \"src/a\"\npart = \"Basics\"\ndraft = true\n[[chapter]]\npath = \"src/b\"\npart = \"Basics\"\n";
+        let without = files(&options(&root), manifest);
+        let with = files(&BookOptions { drafts: true, ..options(&root) }, manifest);
+        fs::remove_dir_all(&root).unwrap();
+
+        let file = |files: &[(String, String)], name: &str| files.iter().find(|(path, _)| path == name).map(|(_, contents)| contents.clone());
+        let names: Vec<&str> = without.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(names, ["mdbook/book.toml", "mdbook/src/a.md", "mdbook/src/b.md", "mdbook/src/SUMMARY.md", "toc.json"]);
+        let summary = "mdbook/src/SUMMARY.md";
+        assert_eq!(file(&without, "mdbook/book.toml").unwrap(), "[book]\ntitle = \"T\"\nauthors = [\"Me\"]\nsrc = \"src\"\n");
+        // Sub-headings nest below their chapter; a draft links nowhere until it is in the book
+        assert_eq!(
+            file(&without, summary).unwrap(),
+            "# Summary\n\n\n# Basics\n\n\
+             - [A]()\n  - [Moves]()\n\
+             - [B](b.md)\n  - [Slices \\[and\\] arrays](b.md#slices-and-arrays)\n    - [Ranges](b.md#ranges)\n  - [Strings](b.md#strings)\n"
+        );
+        assert!(file(&with, summary).unwrap().contains("- [A](a.md)\n  - [Moves](a.md#moves)\n"));
+    }
+}
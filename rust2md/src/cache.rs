@@ -0,0 +1,137 @@
+//
+// What book mode remembers from one build to the next, when builds
+// follow each other in `rust2md watch`: each chapter as it was parsed,
+// and tested with `--run-tests`, by the hash of what was read to do
+// so. That is the chapter's source, or, when its tests are run, every
+// file of its crate. A chapter whose inputs hash the same is neither
+// parsed nor tested again; only the book is put back together.
+//
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diagnostics::Diagnostic;
+use crate::document::Document;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub hash: u64,
+    // Whether the chapter's tests were run
+    pub tested: bool,
+    pub document: Document,
+    // The tests and compile-fail examples that failed
+    pub failures: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Default)]
+pub struct Cache {
+    chapters: HashMap<PathBuf, Entry>,
+    // The chapters built since `take_rebuilt` was last called
+    rebuilt: Vec<PathBuf>,
+}
+
+impl Cache {
+    // The chapter at `source`, if it was built from the same source
+    pub fn get(&self, source: &Path, hash: u64, tested: bool) -> Option<&Entry> {
+        self.chapters.get(source).filter(|entry| entry.hash == hash && entry.tested == tested)
+    }
+
+    pub fn insert(&mut self, source: &Path, entry: Entry) {
+        self.rebuilt.push(source.to_path_buf());
+        self.chapters.insert(source.to_path_buf(), entry);
+    }
+
+    pub fn take_rebuilt(&mut self) -> Vec<PathBuf> {
+        std::mem::take(&mut self.rebuilt)
+    }
+}
+
+//
+// A 64-bit FNV-1a hash, which, unlike `std`'s hasher, is the same from
+// one run to the next
+//
+pub fn hash(bytes: &[u8]) -> u64 {
+    extend(0xcbf2_9ce4_8422_2325, bytes)
+}
+
+fn extend(hash: u64, bytes: &[u8]) -> u64 {
+    bytes.iter().fold(hash, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3))
+}
+
+//
+// The hash of every file below `dir`, names included, leaving out
+// build output (`target`) and hidden files, as `cargo test` would read
+// them
+//
+pub fn hash_files(dir: &Path) -> Result<u64, String> {
+    let mut files = Vec::new();
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let entries = fs::read_dir(&dir).map_err(|e| format!("cannot read '{}': {}", dir.display(), e))?;
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if name == "target" || name.starts_with('.') {
+                continue;
+            }
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    let mut hash = hash(b"");
+    for file in files {
+        let contents = fs::read(&file).map_err(|e| format!("cannot read '{}': {}", file.display(), e))?;
+        let name = file.strip_prefix(dir).unwrap_or(&file).to_string_lossy().into_owned();
+        for part in [name.as_bytes(), &[0], &contents, &[0]] {
+            hash = extend(hash, part);
+        }
+    }
+    Ok(hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entries_are_found_by_hash() {
+        let mut cache = Cache::default();
+        let source = Path::new("src/a/src/main.rs");
+        let entry = Entry { hash: hash(b"// A\n"), tested: false, document: Document::default(), failures: Vec::new() };
+        cache.insert(source, entry.clone());
+        assert_eq!(cache.get(source, hash(b"// A\n"), false), Some(&entry));
+        assert_eq!(cache.get(source, hash(b"// B\n"), false), None);
+        assert_eq!(cache.get(source, hash(b"// A\n"), true), None);
+        assert_eq!(cache.take_rebuilt(), [PathBuf::from(source)]);
+        assert!(cache.take_rebuilt().is_empty());
+        assert_eq!(hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn test_every_file_of_a_crate_counts() {
+        let dir = std::env::temp_dir().join(format!("rust2md-cache-{}", std::process::id()));
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("src/main.rs"), "// A\n").unwrap();
+        fs::write(dir.join("Cargo.toml"), "[package]\n").unwrap();
+        let before = hash_files(&dir);
+        fs::write(dir.join("target/build.log"), "built\n").unwrap();
+        let built = hash_files(&dir);
+        fs::write(dir.join("src/other.rs"), "fn f() {}\n").unwrap();
+        let module = hash_files(&dir);
+        fs::write(dir.join("Cargo.toml"), "[package]\nname = \"a\"\n").unwrap();
+        let manifest = hash_files(&dir);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(before.is_ok());
+        assert_eq!(built, before);
+        assert_ne!(module, built);
+        assert_ne!(manifest, module);
+    }
+}
@@ -0,0 +1,179 @@
+//
+// Examples of code that must not compile. They cannot live in the
+// chapter as code, so they are written in a block comment, right after
+// a directive naming the error expected, if any:
+//
+//     // rust2md: compile-fail E0382
+//     /*
+//     let s1 = String::from("hello");
+//     let s2 = s1;
+//     assert_eq!(s1, "hello");
+//     */
+//
+// The comment becomes a snippet of its own. When the book's tests are
+// run, each example is compiled on its own with the local `rustc`, and
+// the errors it gives are shown after it. An example that compiles, or
+// fails with another error, fails the build.
+//
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::diagnostics::Diagnostic;
+use crate::document::{Block, Document, Snippet};
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CompileFail {
+    // The error code expected, such as `E0382`
+    pub code: Option<String>,
+    // The errors `rustc` gave, once it has been run
+    pub errors: Option<String>,
+}
+
+//
+// Compiles every example of `document`, keeping the errors each gives,
+// and reports those that do not fail as expected
+//
+pub fn check(document: &mut Document) -> Result<Vec<Diagnostic>, String> {
+    let mut problems = Vec::new();
+    for block in &mut document.blocks {
+        let snippet = match block {
+            Block::Code(snippet) if snippet.compile_fail.is_some() => snippet,
+            _ => continue,
+        };
+        let (compiles, errors) = compile(snippet)?;
+        let fail = snippet.compile_fail.as_mut().unwrap();
+        let codes: Vec<&str> = errors.lines().filter_map(code).collect();
+        let problem = match &fail.code {
+            _ if compiles => Some(String::from("example marked compile-fail compiles")),
+            _ if errors.is_empty() => Some(String::from("rustc failed without saying why")),
+            Some(expected) if !codes.contains(&expected.as_str()) && codes.is_empty() => {
+                Some(format!("example fails without an error code, not with {}", expected))
+            }
+            Some(expected) if !codes.contains(&expected.as_str()) => {
+                Some(format!("example fails with {}, not with {}", codes.join(", "), expected))
+            }
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            problems.push(Diagnostic::new(snippet.first_line, problem));
+        }
+        fail.errors = Some(errors);
+    }
+    Ok(problems)
+}
+
+//
+// Whether a snippet compiles as a test crate of its own, and the errors
+// it gives if not, one per line, numbered from the snippet's first line.
+// Statements with no function around them get a `main`. It is rustc's
+// exit status that says whether the snippet compiled: when rustc fails
+// without pointing at a line of it (it cannot write its output, say),
+// the errors are whatever rustc said.
+//
+fn compile(snippet: &Snippet) -> Result<(bool, String), String> {
+    static CRATES: AtomicUsize = AtomicUsize::new(0);
+    let dir: PathBuf = env::temp_dir().join(format!("rust2md-{}-{}", process::id(), CRATES.fetch_add(1, Ordering::Relaxed)));
+    fs::create_dir_all(&dir).map_err(|e| format!("cannot create '{}': {}", dir.display(), e))?;
+
+    const ITEMS: &[&str] = &["fn ", "#[", "struct ", "enum ", "impl ", "trait ", "use ", "mod ", "const ", "static "];
+    let wrapped = !snippet.lines.iter().any(|line| ITEMS.iter().any(|item| line.starts_with(item)));
+    let mut source = snippet.lines.join("\n");
+    if wrapped {
+        source = format!("fn main() {{\n{}\n}}", source);
+    }
+    source.push('\n');
+    let result = fs::write(dir.join("main.rs"), source)
+        .map_err(|e| format!("cannot write to '{}': {}", dir.display(), e))
+        .and_then(|_| {
+            Command::new(env::var("RUSTC").unwrap_or_else(|_| String::from("rustc")))
+                .args(["--edition", "2021", "--test", "-A", "warnings", "--error-format", "short", "--color", "never"])
+                .args(["-o", "main", "main.rs"])
+                .current_dir(&dir)
+                .output()
+                .map_err(|e| format!("cannot run rustc: {}", e))
+        });
+    let _ = fs::remove_dir_all(&dir);
+    let output = result?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut errors = errors(&stderr, if wrapped { 1 } else { 0 });
+    if errors.is_empty() && !output.status.success() {
+        errors = unlocated(&stderr);
+    }
+    Ok((output.status.success(), errors))
+}
+
+//
+// What `rustc --error-format short` says, as in
+//
+//     main.rs:4:5: error[E0382]: borrow of moved value: `s1`
+//
+// made into `line 3: error[E0382]: ...`, counting from the snippet's
+// first line rather than the file's
+//
+fn errors(stderr: &str, offset: usize) -> String {
+    let mut errors = Vec::new();
+    for line in stderr.lines() {
+        let rest = match line.strip_prefix("main.rs:") {
+            Some(rest) => rest,
+            None => continue,
+        };
+        let mut parts = rest.splitn(3, ':');
+        let (number, message) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(number), Some(_), Some(message)) => (number.parse::<usize>().unwrap_or(0), message.trim()),
+            _ => continue,
+        };
+        if message.starts_with("error") {
+            errors.push(format!("line {}: {}", number.saturating_sub(offset), message));
+        }
+    }
+    errors.join("\n")
+}
+
+//
+// The errors rustc gave that do not point at a line, such as
+//
+//     error: linking with `cc` failed: exit status: 1
+//
+// or, if it gave none, everything it said
+//
+fn unlocated(stderr: &str) -> String {
+    let errors: Vec<&str> =
+        stderr.lines().filter(|line| line.starts_with("error") && !line.starts_with("error: aborting due to")).collect();
+    if errors.is_empty() {
+        return stderr.trim().to_string();
+    }
+    errors.join("\n")
+}
+
+// The code of an error, as in `line 3: error[E0382]: ...`
+fn code(error: &str) -> Option<&str> {
+    let start = error.find("error[")? + "error[".len();
+    let end = start + error[start..].find(']')?;
+    Some(&error[start..end])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors() {
+        let stderr = "main.rs:4:5: error[E0382]: borrow of moved value: `s1`\nmain.rs:2:9: warning: unused\n\
+                      error: aborting due to 1 previous error\n";
+        let errors = errors(stderr, 1);
+        assert_eq!(errors, "line 3: error[E0382]: borrow of moved value: `s1`");
+        assert_eq!(code(&errors), Some("E0382"));
+        assert_eq!(super::errors("error: aborting due to 1 previous error\n", 1), "");
+    }
+
+    #[test]
+    fn test_unlocated_errors() {
+        let stderr = "error: linking with `cc` failed: exit status: 1\n  |\n  = note: cc: not found\n\nerror: aborting due to 1 previous error\n";
+        assert_eq!(unlocated(stderr), "error: linking with `cc` failed: exit status: 1");
+        assert_eq!(unlocated("error[E0601]: `main` function not found in crate `main`\n"), "error[E0601]: `main` function not found in crate `main`");
+        assert_eq!(unlocated("thread 'rustc' panicked\n"), "thread 'rustc' panicked");
+    }
+}
@@ -0,0 +1,48 @@
+//
+// Problems found while converting a file. None of them stop the
+// conversion; they are collected in the `Document` for the caller to
+// report. In strict mode the caller treats them as errors.
+//
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(line: usize, message: impl Into<String>) -> Diagnostic {
+        Diagnostic { line, message: message.into() }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+//
+// Prints `diagnostics` to stderr, each prefixed by its location in
+// `source`, as warnings or, in strict mode, as errors. Returns how
+// many there were.
+//
+pub fn print(source: &str, diagnostics: &[Diagnostic], strict: bool) -> usize {
+    let severity = if strict { "error" } else { "warning" };
+    for diagnostic in diagnostics {
+        eprintln!("{}:{}: {}: {}", source, diagnostic.line, severity, diagnostic.message);
+    }
+    diagnostics.len()
+}
+
+//
+// The error that ends a strict run that found `count` problems
+//
+pub fn strict_failure(count: usize) -> String {
+    if count == 1 {
+        String::from("1 warning treated as an error (--strict)")
+    } else {
+        format!("{} warnings treated as errors (--strict)", count)
+    }
+}
@@ -0,0 +1,175 @@
+//
+// Line-based unified diffs, as printed by `diff -u`, used to show why
+// a generated file is out of date
+//
+
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Same(usize, usize),
+    Removed(usize),
+    Added(usize),
+}
+
+//
+// The edits that turn `old` into `new`, from their longest common
+// subsequence
+//
+fn edits(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    // Lines shared at either end do not need the quadratic table
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    let (n, m) = (old_middle.len(), new_middle.len());
+    let mut lcs = vec![0u32; (n + 1) * (m + 1)];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i * (m + 1) + j] = if old_middle[i] == new_middle[j] {
+                lcs[(i + 1) * (m + 1) + j + 1] + 1
+            } else {
+                lcs[(i + 1) * (m + 1) + j].max(lcs[i * (m + 1) + j + 1])
+            };
+        }
+    }
+
+    let mut edits: Vec<Edit> = (0..prefix).map(|i| Edit::Same(i, i)).collect();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old_middle[i] == new_middle[j] {
+            edits.push(Edit::Same(prefix + i, prefix + j));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[(i + 1) * (m + 1) + j] >= lcs[i * (m + 1) + j + 1]) {
+            edits.push(Edit::Removed(prefix + i));
+            i += 1;
+        } else {
+            edits.push(Edit::Added(prefix + j));
+            j += 1;
+        }
+    }
+    for k in 0..suffix {
+        edits.push(Edit::Same(old.len() - suffix + k, new.len() - suffix + k));
+    }
+    edits
+}
+
+//
+// The unified diff between `old` and `new`, or `None` if they are the
+// same
+//
+pub fn unified(old_name: &str, new_name: &str, old: &str, new: &str) -> Option<String> {
+    if old == new {
+        return None;
+    }
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = edits(&old_lines, &new_lines);
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    let changed: Vec<usize> = edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| !matches!(e, Edit::Same(..)))
+        .map(|(i, _)| i)
+        .collect();
+    if changed.is_empty() {
+        // Only the line endings, or the final newline, differ
+        out.push_str("@@ line endings differ @@\n");
+        return Some(out);
+    }
+
+    // Changes closer than twice the context share a hunk
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &index in &changed {
+        let start = index.saturating_sub(CONTEXT);
+        let end = (index + CONTEXT + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if start <= last.1 => last.1 = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+
+    for (start, end) in hunks {
+        let hunk = &edits[start..end];
+        let old_start = first_line(&edits[start..], true);
+        let new_start = first_line(&edits[start..], false);
+        let old_count = hunk.iter().filter(|e| !matches!(e, Edit::Added(_))).count();
+        let new_count = hunk.iter().filter(|e| !matches!(e, Edit::Removed(_))).count();
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            range(old_start, old_count),
+            range(new_start, new_count)
+        ));
+        for edit in hunk {
+            match *edit {
+                Edit::Same(i, _) => out.push_str(&format!(" {}\n", old_lines[i])),
+                Edit::Removed(i) => out.push_str(&format!("-{}\n", old_lines[i])),
+                Edit::Added(j) => out.push_str(&format!("+{}\n", new_lines[j])),
+            }
+        }
+    }
+    Some(out)
+}
+
+// The 0-based line of the old (or new) file a hunk starts at
+fn first_line(edits: &[Edit], old: bool) -> usize {
+    for edit in edits {
+        match (*edit, old) {
+            (Edit::Same(i, _), true) | (Edit::Removed(i), true) => return i,
+            (Edit::Same(_, j), false) | (Edit::Added(j), false) => return j,
+            _ => {}
+        }
+    }
+    0
+}
+
+// A hunk range as `diff -u` writes it: 1-based, empty ranges at the
+// line before
+fn range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_text_has_no_diff() {
+        assert_eq!(unified("a", "b", "x\ny\n", "x\ny\n"), None);
+    }
+
+    #[test]
+    fn test_changed_line() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        assert_eq!(
+            unified("old", "new", old, new).unwrap(),
+            "--- old\n+++ new\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+
+    #[test]
+    fn test_separate_hunks() {
+        let old: String = (1..=20).map(|i| format!("{}\n", i)).collect();
+        let new: String = (1..=20)
+            .filter(|&i| i != 19)
+            .map(|i| if i == 2 { "two\n".to_string() } else { format!("{}\n", i) })
+            .collect();
+        let diff = unified("old", "new", &old, &new).unwrap();
+        assert_eq!(diff.matches("@@ -").count(), 2);
+        assert!(diff.contains("@@ -1,5 +1,5 @@\n 1\n-2\n+two\n"));
+        assert!(diff.contains("@@ -16,5 +16,4 @@\n 16\n 17\n 18\n-19\n 20\n"));
+    }
+
+    #[test]
+    fn test_new_file() {
+        assert_eq!(unified("a", "b", "", "x\n").unwrap(), "--- a\n+++ b\n@@ -0,0 +1 @@\n+x\n");
+    }
+}
@@ -0,0 +1,289 @@
+//
+// What the parser makes of a Rust file: a sequence of prose and code
+// blocks, each remembering the source lines it came from. Every output
+// format is rendered from a `Document`.
+//
+use std::borrow::Cow;
+use std::io::{self, Write};
+
+use crate::compile_fail::CompileFail;
+use crate::diagnostics::Diagnostic;
+use crate::html;
+use crate::libtest::Outcome;
+use crate::region::Region;
+use crate::scaffolding::{self, Scaffolding};
+use crate::{Fences, Options};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Prose(Prose),
+    Code(Snippet),
+    Include(Include),
+}
+
+//
+// Markdown text taken from consecutive `//` comments. Empty comments
+// become empty lines.
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prose {
+    pub lines: Vec<String>,
+    pub first_line: usize,
+}
+
+//
+// Code found between two comments, already dedented to the column of
+// the comment before it
+//
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Snippet {
+    pub lines: Vec<String>,
+    pub first_line: usize,
+    // Whether a `// rust2md: full` directive keeps the test scaffolding
+    pub full: bool,
+    // The summary of the `<details>` block a `// rust2md: collapse`
+    // directive folds the snippet into
+    pub summary: Option<String>,
+    // The file the code came from, when it was included from another
+    // one (otherwise, `Options::source`)
+    pub file: Option<String>,
+    // The tests the snippet defines, once they have been run (see
+    // `libtest`)
+    pub tests: Vec<(String, Outcome)>,
+    // Whether the snippet is an example that must not compile
+    pub compile_fail: Option<CompileFail>,
+}
+
+//
+// A `// rust2md: include chapter#region` directive, which becomes a
+// snippet once the region is found (see `region::include`)
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct Include {
+    pub target: String,
+    pub line: usize,
+}
+
+impl Snippet {
+    pub fn last_line(&self) -> usize {
+        self.first_line + self.lines.len().saturating_sub(1)
+    }
+
+    //
+    // How the snippet's tests went as a whole: failed if any failed,
+    // passed if any passed, ignored if all of them were
+    //
+    pub fn outcome(&self) -> Option<Outcome> {
+        let outcomes = || self.tests.iter().map(|(_, outcome)| *outcome);
+        if self.tests.is_empty() {
+            None
+        } else if outcomes().any(|o| o == Outcome::Failed) {
+            Some(Outcome::Failed)
+        } else if outcomes().any(|o| o == Outcome::Passed) {
+            Some(Outcome::Passed)
+        } else {
+            Some(Outcome::Ignored)
+        }
+    }
+
+    // What the compiler said about a compile-fail example
+    pub fn errors(&self) -> Option<&str> {
+        self.compile_fail.as_ref()?.errors.as_deref().filter(|errors| !errors.is_empty())
+    }
+
+    // The lines the snippet shows, given `Options::test_scaffolding`
+    pub fn shown(&self, options: &Options) -> Cow<'_, [String]> {
+        if self.stripped(options) {
+            return Cow::Owned(scaffolding::strip(&self.lines, options.tab_width).into_iter().map(|(_, line)| line).collect());
+        }
+        Cow::Borrowed(&self.lines)
+    }
+
+    //
+    // The first and last source lines of the lines the snippet shows,
+    // which, once tests are unwrapped, are not those of the snippet
+    //
+    pub fn shown_lines(&self, options: &Options) -> (usize, usize) {
+        if !self.stripped(options) {
+            return (self.first_line, self.last_line());
+        }
+        let stripped = scaffolding::strip(&self.lines, options.tab_width);
+        let mut sources = stripped.iter().filter_map(|(source, _)| *source);
+        let first = sources.next().unwrap_or(0);
+        let last = sources.next_back().unwrap_or(first);
+        (self.first_line + first, self.first_line + last)
+    }
+
+    fn stripped(&self, options: &Options) -> bool {
+        !self.full && options.test_scaffolding == Scaffolding::Strip
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Document {
+    pub blocks: Vec<Block>,
+    // The regions marked with `// region: name`, for other snippets to
+    // include
+    pub regions: Vec<Region>,
+    // How many lines the source had
+    pub lines: usize,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    pub fn is_empty(&self) -> bool {
+        self.blocks.is_empty()
+    }
+
+    pub fn snippets(&self) -> impl Iterator<Item = &Snippet> {
+        self.blocks.iter().filter_map(|block| match block {
+            Block::Code(snippet) => Some(snippet),
+            _ => None,
+        })
+    }
+
+    //
+    // The line of the markdown each snippet's opening fence is written
+    // on, counting from 1
+    //
+    pub fn fence_lines(&self, options: &Options) -> Vec<usize> {
+        let mut fences = Vec::new();
+        let mut line = 1;
+        for block in &self.blocks {
+            match block {
+                Block::Prose(prose) => line += prose.lines.len(),
+                Block::Code(snippet) => {
+                    // `<details>` and `<summary>` come before, `</details>` after
+                    let (before, after) = if snippet.summary.is_some() { (3, 2) } else { (0, 0) };
+                    let badge = if snippet.outcome().is_some() { 2 } else { 0 };
+                    let errors = snippet.errors().map_or(0, |errors| errors.lines().count() + 3);
+                    fences.push(line + 1 + before);
+                    line += before + snippet.shown(options).len() + 4 + badge + errors + after;
+                }
+                Block::Include(_) => {}
+            }
+        }
+        fences
+    }
+
+    pub fn write_markdown<W: Write>(&self, out: &mut W, options: &Options) -> io::Result<()> {
+        for block in &self.blocks {
+            match block {
+                Block::Prose(prose) => {
+                    for line in &prose.lines {
+                        writeln!(out, "{}", line)?;
+                    }
+                }
+                Block::Code(snippet) => {
+                    writeln!(out)?;
+                    if let Some(summary) = &snippet.summary {
+                        writeln!(out, "<details>\n<summary>{}</summary>\n", html::escape(summary))?;
+                    }
+                    writeln!(out, "``` {}", fence_info(snippet, options))?;
+                    for line in snippet.shown(options).iter() {
+                        writeln!(out, "{}", line)?;
+                    }
+                    writeln!(out, "```")?;
+                    writeln!(out)?;
+                    if let Some(badge) = badge(snippet) {
+                        writeln!(out, "{}\n", badge)?;
+                    }
+                    if let Some(errors) = snippet.errors() {
+                        writeln!(out, "``` text\n{}\n```\n", errors)?;
+                    }
+                    if snippet.summary.is_some() {
+                        writeln!(out, "</details>\n")?;
+                    }
+                }
+                // Includes that were never resolved have nothing to show
+                Block::Include(_) => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+//
+// How a snippet's tests went, as a line of HTML after it, with the
+// outcome of each test in its title
+//
+fn badge(snippet: &Snippet) -> Option<String> {
+    let outcome = snippet.outcome()?;
+    let symbol = match outcome {
+        Outcome::Passed => "✔",
+        Outcome::Failed => "✘",
+        Outcome::Ignored => "○",
+    };
+    let title: Vec<String> = snippet.tests.iter().map(|(name, outcome)| format!("{}: {}", name, outcome.name())).collect();
+    Some(format!(
+        "<p class=\"test {}\" title=\"{}\">{} {}</p>",
+        outcome.name(),
+        html::escape(&title.join(", ")),
+        symbol,
+        outcome.name()
+    ))
+}
+
+//
+// The info string of a snippet's fence: the language, or attributes
+// saying that the snippet must not compile and, with
+// `Options::source_lines`, where the code came from. mdBook fences say
+// what its tests need to know instead.
+//
+fn fence_info(snippet: &Snippet, options: &Options) -> String {
+    if options.fences == Fences::MdBook {
+        let mut info = vec![options.language.as_str()];
+        info.extend(mdbook_attributes(snippet));
+        return info.join(",");
+    }
+    if !options.source_lines && snippet.compile_fail.is_none() {
+        return options.language.clone();
+    }
+    let mut attributes = Vec::new();
+    if !options.language.is_empty() {
+        attributes.push(format!(".{}", options.language));
+    }
+    if snippet.compile_fail.is_some() {
+        attributes.push(String::from(".compile_fail"));
+    }
+    if options.source_lines {
+        let (first, last) = snippet.shown_lines(options);
+        attributes.push(format!("startFrom={}", first));
+        // Where lines were taken out, the shown ones do not end where
+        // their count says
+        if last + 1 != first + snippet.shown(options).len() {
+            attributes.push(format!("endAt={}", last));
+        }
+        if let Some(source) = snippet.file.as_ref().or(options.source.as_ref()) {
+            if source.contains(|c: char| c.is_whitespace() || c == '}') {
+                attributes.push(format!("file=\"{}\"", source.replace('"', "\\\"")));
+            } else {
+                attributes.push(format!("file={}", source));
+            }
+        }
+    }
+    format!("{{{}}}", attributes.join(" "))
+}
+
+//
+// The attributes of the tests in a snippet, as mdBook names them:
+// `#[should_panic]` and `#[ignore]` tests, and compile-fail examples
+//
+fn mdbook_attributes(snippet: &Snippet) -> Vec<&'static str> {
+    let has = |attribute: &str| {
+        snippet.lines.iter().map(|line| line.trim()).any(|line| {
+            line.strip_prefix("#[").and_then(|rest| rest.strip_prefix(attribute)).is_some_and(|rest| rest.starts_with([']', '(', ' ', '=']))
+        })
+    };
+    let mut attributes = Vec::new();
+    if has("ignore") {
+        attributes.push("ignore");
+    }
+    if has("should_panic") {
+        attributes.push("should_panic");
+    }
+    if snippet.compile_fail.is_some() {
+        attributes.push("compile_fail");
+    }
+    attributes
+}
@@ -0,0 +1,274 @@
+//
+// The book as an EPUB 3 file, to be read offline: a title page with
+// the book's metadata, then a page for each chapter, rendered as for
+// the HTML output but as XHTML, with the stylesheet and a navigation
+// document made from the table of contents. An EPUB is a ZIP archive;
+// the one written here stores its files as they are, which every
+// reader can open, and which keeps the archive the same from one
+// build to the next.
+//
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cache;
+use crate::html::{self, escape};
+use crate::manifest::Metadata;
+use crate::toc::{self, Entry, Heading};
+
+// The chapter pages, title page and navigation document live here
+const CONTENT_DIR: &str = "EPUB";
+const CONTAINER: &str = "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+<container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+  <rootfiles>\n\
+    <rootfile full-path=\"EPUB/package.opf\" media-type=\"application/oebps-package+xml\" />\n\
+  </rootfiles>\n\
+</container>\n";
+
+pub struct Chapter<'a> {
+    // The name of its page, without `.xhtml`
+    pub name: &'a str,
+    pub title: String,
+    // The chapter as XHTML, with links to other chapters' `.xhtml` pages
+    pub body: String,
+    pub headings: &'a [Heading],
+}
+
+pub fn package(metadata: &Metadata, chapters: &[Chapter]) -> Vec<u8> {
+    let title = metadata.title.as_deref().unwrap_or("Untitled");
+    let mut files = vec![
+        // The first file, stored as it is, tells readers what the archive is
+        (String::from("mimetype"), b"application/epub+zip".to_vec()),
+        (String::from("META-INF/container.xml"), CONTAINER.as_bytes().to_vec()),
+        (format!("{}/package.opf", CONTENT_DIR), package_document(metadata, chapters).into_bytes()),
+        (format!("{}/nav.xhtml", CONTENT_DIR), navigation(title, chapters).into_bytes()),
+        (format!("{}/style.css", CONTENT_DIR), html::STYLE.as_bytes().to_vec()),
+        (format!("{}/title.xhtml", CONTENT_DIR), page(title, &title_page(metadata)).into_bytes()),
+    ];
+    for chapter in chapters {
+        files.push((format!("{}/{}.xhtml", CONTENT_DIR, chapter.name), page(&chapter.title, &chapter.body).into_bytes()));
+    }
+    zip(&files)
+}
+
+//
+// The package document: what the book is, what files it is made of,
+// and the order they are read in
+//
+fn package_document(metadata: &Metadata, chapters: &[Chapter]) -> String {
+    let title = metadata.title.as_deref().unwrap_or("Untitled");
+    let identity = format!("{}\n{}", title, metadata.author.as_deref().unwrap_or_default());
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    out.push_str("<package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"en\">\n");
+    out.push_str("  <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n");
+    out.push_str(&format!("    <dc:identifier id=\"book-id\">urn:rust2md:{:016x}</dc:identifier>\n", cache::hash(identity.as_bytes())));
+    out.push_str(&format!("    <dc:title>{}</dc:title>\n", escape(title)));
+    if let Some(author) = &metadata.author {
+        out.push_str(&format!("    <dc:creator>{}</dc:creator>\n", escape(author)));
+    }
+    if let Some(date) = &metadata.date {
+        out.push_str(&format!("    <dc:date>{}</dc:date>\n", escape(date)));
+    }
+    out.push_str("    <dc:language>en</dc:language>\n");
+    out.push_str(&format!("    <meta property=\"dcterms:modified\">{}</meta>\n", modified(metadata)));
+    out.push_str("  </metadata>\n  <manifest>\n");
+    out.push_str("    <item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\" />\n");
+    out.push_str("    <item id=\"style\" href=\"style.css\" media-type=\"text/css\" />\n");
+    out.push_str("    <item id=\"title\" href=\"title.xhtml\" media-type=\"application/xhtml+xml\" />\n");
+    for (n, chapter) in chapters.iter().enumerate() {
+        out.push_str(&format!(
+            "    <item id=\"chapter-{}\" href=\"{}.xhtml\" media-type=\"application/xhtml+xml\" />\n",
+            n + 1,
+            escape(chapter.name)
+        ));
+    }
+    out.push_str("  </manifest>\n  <spine>\n    <itemref idref=\"title\" />\n    <itemref idref=\"nav\" />\n");
+    for n in 1..=chapters.len() {
+        out.push_str(&format!("    <itemref idref=\"chapter-{}\" />\n", n));
+    }
+    out.push_str("  </spine>\n</package>\n");
+    out
+}
+
+//
+// When the book last changed, which EPUB 3 requires: the book's date,
+// if it has one, or else now
+//
+fn modified(metadata: &Metadata) -> String {
+    let date = metadata.date.as_deref().filter(|date| {
+        let parts: Vec<&str> = date.split('-').collect();
+        parts.len() == 3 && parts.iter().zip([4, 2, 2]).all(|(part, len)| part.len() == len && part.bytes().all(|b| b.is_ascii_digit()))
+    });
+    if let Some(date) = date {
+        return format!("{}T00:00:00Z", date);
+    }
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_date(seconds / 86400);
+    let time = seconds % 86400;
+    format!("{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, time / 3600, time / 60 % 60, time % 60)
+}
+
+// The year, month and day `days` after 1970-01-01
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+//
+// The navigation document: the chapters, and their headings down to
+// `toc::DEPTH`, as nested ordered lists
+//
+fn navigation(title: &str, chapters: &[Chapter]) -> String {
+    let mut body = String::from("<nav epub:type=\"toc\" id=\"toc\" role=\"doc-toc\">\n<h1>Contents</h1>\n<ol>\n");
+    for chapter in chapters {
+        let page = format!("{}.xhtml", chapter.name);
+        body.push_str(&format!("<li><a href=\"{}\">{}</a>", escape(&page), escape(&chapter.title)));
+        //
+        // The chapter's own title is the chapter's entry; what is under
+        // it goes below
+        //
+        let entries = toc::shallow(chapter.headings, toc::DEPTH);
+        let entries: Vec<&Entry> = match entries.as_slice() {
+            [only] if only.heading.level == 1 => only.children.iter().collect(),
+            entries => entries.iter().collect(),
+        };
+        nav_list(&entries, &page, &mut body);
+        body.push_str("</li>\n");
+    }
+    body.push_str("</ol>\n</nav>\n");
+    page(title, &body)
+}
+
+fn nav_list(entries: &[&Entry], page: &str, out: &mut String) {
+    if entries.is_empty() {
+        return;
+    }
+    out.push_str("\n<ol>\n");
+    for entry in entries {
+        let heading = entry.heading;
+        out.push_str(&format!("<li><a href=\"{}#{}\">{}</a>", escape(page), escape(&heading.anchor), escape(&heading.text)));
+        nav_list(&entry.children.iter().collect::<Vec<_>>(), page, out);
+        out.push_str("</li>\n");
+    }
+    out.push_str("</ol>\n");
+}
+
+fn title_page(metadata: &Metadata) -> String {
+    let mut out = String::from("<header id=\"title-block-header\">\n");
+    for (class, value) in [("title", &metadata.title), ("author", &metadata.author), ("date", &metadata.date)] {
+        if let Some(value) = value {
+            let tag = if class == "title" { "h1" } else { "p" };
+            out.push_str(&format!("<{} class=\"{}\">{}</{}>\n", tag, class, escape(value), tag));
+        }
+    }
+    out.push_str("</header>\n");
+    out
+}
+
+fn page(title: &str, body: &str) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n<!DOCTYPE html>\n");
+    out.push_str("<html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"en\" xml:lang=\"en\">\n");
+    out.push_str("<head>\n  <meta charset=\"utf-8\" />\n");
+    out.push_str(&format!("  <title>{}</title>\n", escape(title)));
+    out.push_str("  <link rel=\"stylesheet\" type=\"text/css\" href=\"style.css\" />\n</head>\n<body>\n");
+    out.push_str(body);
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+//
+// A ZIP archive of `files`, each stored without compression. Every
+// file gets the earliest date ZIP knows, 1980-01-01.
+//
+fn zip(files: &[(String, Vec<u8>)]) -> Vec<u8> {
+    const TIME: u16 = 0;
+    const DATE: u16 = 1 << 5 | 1;
+    let mut out = Vec::new();
+    let mut directory = Vec::new();
+    for (name, contents) in files {
+        let offset = out.len() as u32;
+        let crc = crc32(contents);
+        let size = contents.len() as u32;
+        // The local file header, then the file
+        out.extend(0x0403_4b50u32.to_le_bytes());
+        for field in [20, 0, 0, TIME, DATE] {
+            out.extend(u16::to_le_bytes(field));
+        }
+        for field in [crc, size, size] {
+            out.extend(field.to_le_bytes());
+        }
+        out.extend((name.len() as u16).to_le_bytes());
+        out.extend(0u16.to_le_bytes());
+        out.extend(name.as_bytes());
+        out.extend(contents);
+        // Its entry in the central directory
+        directory.extend(0x0201_4b50u32.to_le_bytes());
+        for field in [20, 20, 0, 0, TIME, DATE] {
+            directory.extend(u16::to_le_bytes(field));
+        }
+        for field in [crc, size, size] {
+            directory.extend(field.to_le_bytes());
+        }
+        for field in [name.len() as u16, 0, 0, 0, 0] {
+            directory.extend(field.to_le_bytes());
+        }
+        for field in [0, offset] {
+            directory.extend(u32::to_le_bytes(field));
+        }
+        directory.extend(name.as_bytes());
+    }
+    let (offset, size) = (out.len() as u32, directory.len() as u32);
+    out.extend(directory);
+    // The end of the central directory
+    out.extend(0x0605_4b50u32.to_le_bytes());
+    for field in [0, 0, files.len() as u16, files.len() as u16] {
+        out.extend(u16::to_le_bytes(field));
+    }
+    for field in [size, offset] {
+        out.extend(field.to_le_bytes());
+    }
+    out.extend(0u16.to_le_bytes());
+    out
+}
+
+// The CRC-32 ZIP checks each file with
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archive() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        let epub = package(&Metadata { title: Some(String::from("Book")), ..Metadata::default() }, &[]);
+        // The mimetype comes first, stored as it is, right after its header
+        assert_eq!(&epub[..4], b"PK\x03\x04");
+        assert_eq!(&epub[30..58], b"mimetypeapplication/epub+zip");
+        assert_eq!(&epub[epub.len() - 22..epub.len() - 18], b"PK\x05\x06");
+        assert_eq!(epub[epub.len() - 12], 6);
+    }
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(20_137), (2025, 2, 18));
+        let metadata = Metadata { date: Some(String::from("2025-02-18")), ..Metadata::default() };
+        assert_eq!(modified(&metadata), "2025-02-18T00:00:00Z");
+    }
+}
@@ -0,0 +1,137 @@
+//
+// Rust syntax highlighting for the HTML output. It is built on the
+// lexer, so there are no grammars to fetch. Tokens are wrapped in
+// spans whose classes the stylesheet colours:
+//
+//     kw    keywords
+//     lit   numbers, strings, characters, `true` and `false`
+//     mac   macro names, such as `assert_eq!`
+//     lt    lifetimes
+//     attr  attributes, such as `#[test]`
+//     com   comments, doc comments included
+//
+// Spans never cross lines: a block comment or attribute that does is
+// closed at the end of each line and opened again on the next.
+//
+use crate::html::escape;
+use crate::lexer::{Lexer, Token, TokenKind};
+
+pub const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern", "fn", "for",
+    "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return", "self", "Self",
+    "static", "struct", "super", "trait", "type", "union", "unsafe", "use", "where", "while", "yield",
+];
+
+pub fn rust(code: &str) -> String {
+    let mut lexer = Lexer::new();
+    // The brackets still open in the attribute being highlighted
+    let mut attribute: Option<usize> = None;
+    let mut out = String::new();
+    for line in code.split_inclusive('\n') {
+        let text = line.strip_suffix('\n').unwrap_or(line);
+        let tokens = lexer.line(text);
+        if attribute.is_some() {
+            out.push_str("<span class=\"attr\">");
+        }
+        let mut n = 0;
+        while n < tokens.len() {
+            let token = tokens[n];
+            let token_text = token.text(text);
+            n += 1;
+
+            if let Some(depth) = attribute.as_mut() {
+                out.push_str(&escape(token_text));
+                match token_text {
+                    "[" => *depth += 1,
+                    "]" => {
+                        *depth -= 1;
+                        if *depth == 0 {
+                            out.push_str("</span>");
+                            attribute = None;
+                        }
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            if token_text == "#" && starts_attribute(text, &tokens[n..]) {
+                out.push_str("<span class=\"attr\">#");
+                attribute = Some(0);
+                continue;
+            }
+
+            let class = match token.kind {
+                kind if kind.is_comment() => Some("com"),
+                TokenKind::Str | TokenKind::RawStr | TokenKind::Char | TokenKind::Number => Some("lit"),
+                TokenKind::Lifetime => Some("lt"),
+                TokenKind::Ident if is_macro(text, &tokens[n..]) => {
+                    // The `!` goes with the name
+                    n += 1;
+                    out.push_str(&format!("<span class=\"mac\">{}!</span>", escape(token_text)));
+                    continue;
+                }
+                TokenKind::Ident if matches!(token_text, "true" | "false") => Some("lit"),
+                TokenKind::Ident if KEYWORDS.contains(&token_text) => Some("kw"),
+                _ => None,
+            };
+            match class {
+                Some(class) => out.push_str(&format!("<span class=\"{}\">{}</span>", class, escape(token_text))),
+                None => out.push_str(&escape(token_text)),
+            }
+        }
+        if attribute.is_some() {
+            out.push_str("</span>");
+        }
+        if line.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+// `#` followed by `[`, or by `![`
+fn starts_attribute(line: &str, rest: &[Token]) -> bool {
+    let texts: Vec<&str> = rest.iter().take(2).map(|t| t.text(line)).collect();
+    matches!(texts.as_slice(), ["[", ..] | ["!", "["])
+}
+
+// A name followed by `!`, but not by `!=`
+fn is_macro(line: &str, rest: &[Token]) -> bool {
+    let texts: Vec<&str> = rest.iter().take(2).map(|t| t.text(line)).collect();
+    matches!(texts.as_slice(), ["!"] | ["!", _]) && texts.get(1) != Some(&"=")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens_get_classes() {
+        assert_eq!(
+            rust("let s: &'a str = \"x\"; // y\n"),
+            "<span class=\"kw\">let</span> s: &amp;<span class=\"lt\">'a</span> str = \
+             <span class=\"lit\">&quot;x&quot;</span>; <span class=\"com\">// y</span>\n"
+        );
+    }
+
+    #[test]
+    fn test_macros_and_attributes() {
+        assert_eq!(
+            rust("#[test]\nassert_eq!(a != b, true);\n"),
+            "<span class=\"attr\">#[test]</span>\n\
+             <span class=\"mac\">assert_eq!</span>(a != b, <span class=\"lit\">true</span>);\n"
+        );
+        assert_eq!(
+            rust("#![allow(\n  dead_code)]\n"),
+            "<span class=\"attr\">#![allow(</span>\n<span class=\"attr\">  dead_code)]</span>\n"
+        );
+    }
+
+    #[test]
+    fn test_block_comments_span_lines() {
+        assert_eq!(
+            rust("/* a\n b */ 1\n"),
+            "<span class=\"com\">/* a</span>\n<span class=\"com\"> b */</span> <span class=\"lit\">1</span>\n"
+        );
+    }
+}
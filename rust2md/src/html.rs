@@ -0,0 +1,325 @@
+//
+// Renders parsed markdown as HTML, and wraps it in standalone pages
+// with an embedded stylesheet and a table of contents, so the book is
+// published without pandoc or any other tool
+//
+use crate::highlight;
+use crate::manifest::{Metadata, Theme};
+use crate::markdown::{self, Block, Inline};
+use crate::toc::{self, Entry, Heading};
+use crate::xhtml;
+
+pub const STYLE: &str = include_str!("style.css");
+
+// Where a snippet's source can be edited, given its file and first and
+// last lines
+pub type SourceLink<'a> = &'a dyn Fn(&str, usize, usize) -> Option<String>;
+
+//
+// Renders the blocks of one chapter. Headings get the anchors worked
+// out for the table of contents, in the same order.
+//
+pub struct Renderer<'a> {
+    headings: std::slice::Iter<'a, Heading>,
+    source_link: Option<SourceLink<'a>>,
+    xhtml: bool,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(headings: &'a [Heading]) -> Renderer<'a> {
+        Renderer { headings: headings.iter(), source_link: None, xhtml: false }
+    }
+
+    //
+    // Follows every snippet whose fence says where it came from (see
+    // `Options::source_lines`) with a link to edit its source
+    //
+    pub fn with_source_links(self, link: SourceLink<'a>) -> Renderer<'a> {
+        Renderer { source_link: Some(link), ..self }
+    }
+
+    //
+    // Writes XHTML, as the EPUB output wants, making the raw HTML that
+    // markdown lets through well-formed (see `xhtml`)
+    //
+    pub fn xhtml(self) -> Renderer<'a> {
+        Renderer { xhtml: true, ..self }
+    }
+
+    pub fn blocks(&mut self, blocks: &[Block]) -> String {
+        let out = self.body(blocks);
+        // Tags may open in one block and close in another, so the
+        // whole of it is made well-formed at once
+        if self.xhtml {
+            xhtml::from_html(&out)
+        } else {
+            out
+        }
+    }
+
+    fn body(&mut self, blocks: &[Block]) -> String {
+        let mut out = String::new();
+        for block in blocks {
+            self.block(block, &mut out);
+        }
+        out
+    }
+
+    fn block(&mut self, block: &Block, out: &mut String) {
+        match block {
+            Block::Heading { level, text } => {
+                let id = self.headings.next().map(|h| format!(" id=\"{}\"", escape(&h.anchor))).unwrap_or_default();
+                out.push_str(&format!("<h{}{}>{}</h{}>\n", level, id, inlines(text), level));
+            }
+            Block::Paragraph(text) => out.push_str(&format!("<p>{}</p>\n", inlines(text))),
+            Block::Code { info, text } => {
+                let language = markdown::language(info);
+                let class = language.map(|l| format!(" class=\"language-{}\"", escape(l))).unwrap_or_default();
+                let code = match language {
+                    Some("rust") => highlight::rust(text),
+                    _ => escape(text),
+                };
+                // Examples that must not compile are marked as such
+                let pre = if info.split_whitespace().any(|word| word.trim_matches(['{', '}']) == ".compile_fail") {
+                    "<pre class=\"compile-fail\">"
+                } else {
+                    "<pre>"
+                };
+                out.push_str(&format!("{}<code{}>{}</code></pre>\n", pre, class, code));
+                if let Some(link) = self.source_link(info, text) {
+                    out.push_str(&link);
+                }
+            }
+            Block::Quote(blocks) => {
+                out.push_str("<blockquote>\n");
+                out.push_str(&self.body(blocks));
+                out.push_str("</blockquote>\n");
+            }
+            Block::List { start, tight, items } => {
+                let tag = match start {
+                    Some(1) => String::from("<ol>"),
+                    Some(n) => format!("<ol start=\"{}\">", n),
+                    None => String::from("<ul>"),
+                };
+                out.push_str(&tag);
+                out.push('\n');
+                for item in items {
+                    out.push_str("<li>");
+                    self.item(item, *tight, out);
+                    out.push_str("</li>\n");
+                }
+                out.push_str(if start.is_some() { "</ol>\n" } else { "</ul>\n" });
+            }
+            Block::Rule => out.push_str("<hr />\n"),
+            Block::Html(html) => {
+                out.push_str(html);
+                out.push('\n');
+            }
+        }
+    }
+
+    fn source_link(&self, info: &str, text: &str) -> Option<String> {
+        let link = self.source_link?;
+        let file = markdown::attribute(info, "file")?;
+        let start: usize = markdown::attribute(info, "startFrom")?.parse().ok()?;
+        let end = match markdown::attribute(info, "endAt") {
+            Some(end) => end.parse().ok()?,
+            None => start + text.lines().count().saturating_sub(1),
+        };
+        let url = link(&file, start, end)?;
+        Some(format!(
+            "<p class=\"edit-source\"><a href=\"{}\" title=\"{}, lines {}-{}\">Edit source</a></p>\n",
+            escape(&url),
+            escape(&file),
+            start,
+            end
+        ))
+    }
+
+    // The paragraphs of a tight list item go without `<p>`
+    fn item(&mut self, item: &[Block], tight: bool, out: &mut String) {
+        for (n, block) in item.iter().enumerate() {
+            match block {
+                Block::Paragraph(text) if tight => {
+                    out.push_str(&inlines(text));
+                    if n + 1 < item.len() {
+                        out.push('\n');
+                    }
+                }
+                _ => {
+                    if n == 0 {
+                        out.push('\n');
+                    }
+                    self.block(block, out);
+                }
+            }
+        }
+    }
+}
+
+pub fn inlines(inlines: &[Inline]) -> String {
+    let mut out = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => out.push_str(&escape(text)),
+            Inline::Code(code) => out.push_str(&format!("<code>{}</code>", escape(code))),
+            Inline::Emphasis(inner) => out.push_str(&format!("<em>{}</em>", self::inlines(inner))),
+            Inline::Strong(inner) => out.push_str(&format!("<strong>{}</strong>", self::inlines(inner))),
+            Inline::Link { url, title, text } => {
+                out.push_str(&format!("<a href=\"{}\"{}>{}</a>", escape(url), title_attribute(title), self::inlines(text)));
+            }
+            Inline::Image { url, title, alt } => {
+                out.push_str(&format!("<img src=\"{}\" alt=\"{}\"{} />", escape(url), escape(&toc::plain(alt)), title_attribute(title)));
+            }
+            Inline::Html(html) => out.push_str(html),
+            Inline::SoftBreak => out.push('\n'),
+            Inline::HardBreak => out.push_str("<br />\n"),
+        }
+    }
+    out
+}
+
+fn title_attribute(title: &Option<String>) -> String {
+    title.as_ref().map(|t| format!(" title=\"{}\"", escape(t))).unwrap_or_default()
+}
+
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+//
+// The table of contents as nested lists, each heading linking to the
+// anchor given by `link`
+//
+pub fn toc(entries: &[Entry], link: &dyn Fn(&Heading) -> String) -> String {
+    if entries.is_empty() {
+        return String::new();
+    }
+    let mut out = String::from("<nav id=\"TOC\" role=\"doc-toc\">\n");
+    toc_list(entries, link, &mut out);
+    out.push_str("\n</nav>\n");
+    out
+}
+
+fn toc_list(entries: &[Entry], link: &dyn Fn(&Heading) -> String, out: &mut String) {
+    out.push_str("<ul>\n");
+    for entry in entries {
+        let heading = entry.heading;
+        out.push_str(&format!("<li><a href=\"{}\">{}</a>", escape(&link(heading)), escape(&heading.text)));
+        if !entry.children.is_empty() {
+            out.push('\n');
+            toc_list(&entry.children, link, out);
+        }
+        out.push_str("</li>\n");
+    }
+    out.push_str("</ul>");
+}
+
+//
+// A standalone page: the metadata as a title block, then `nav` (the
+// table of contents, links to other pages...), then the body, in the
+// colours of `theme`
+//
+pub fn page(title: &str, metadata: &Metadata, theme: Theme, nav: &str, body: &str) -> String {
+    let class = match theme {
+        Theme::Auto => "",
+        Theme::Light => " class=\"light\"",
+        Theme::Dark => " class=\"dark\"",
+    };
+    let mut out = format!("<!DOCTYPE html>\n<html lang=\"en\"{}>\n<head>\n", class);
+    out.push_str("  <meta charset=\"utf-8\" />\n");
+    out.push_str("  <meta name=\"generator\" content=\"rust2md\" />\n");
+    out.push_str("  <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0, user-scalable=yes\" />\n");
+    out.push_str(&format!("  <title>{}</title>\n", escape(title)));
+    out.push_str("  <style>\n");
+    for line in STYLE.lines() {
+        if !line.is_empty() {
+            out.push_str("    ");
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    out.push_str("  </style>\n</head>\n<body>\n");
+    if !metadata.is_empty() {
+        out.push_str("<header id=\"title-block-header\">\n");
+        for (class, value) in [("title", &metadata.title), ("author", &metadata.author), ("date", &metadata.date)] {
+            if let Some(value) = value {
+                let tag = if class == "title" { "h1" } else { "p" };
+                out.push_str(&format!("<{} class=\"{}\">{}</{}>\n", tag, class, escape(value), tag));
+            }
+        }
+        out.push_str("</header>\n");
+    }
+    out.push_str(nav);
+    out.push_str(body);
+    out.push_str("</body>\n</html>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown;
+    use crate::toc::Anchors;
+
+    fn render(text: &str) -> String {
+        let blocks = markdown::parse(text);
+        let headings = toc::headings(&blocks, "chapter", &mut Anchors::default());
+        Renderer::new(&headings).blocks(&blocks)
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(
+            render("# Data & Types\n\nA `<T>` *value*.\n\n``` rust\nlet x = 1 < 2;\n```\n"),
+            "<h1 id=\"data-types\">Data &amp; Types</h1>\n\
+             <p>A <code>&lt;T&gt;</code> <em>value</em>.</p>\n\
+             <pre><code class=\"language-rust\"><span class=\"kw\">let</span> x = <span class=\"lit\">1</span> &lt; <span class=\"lit\">2</span>;\n</code></pre>\n"
+        );
+    }
+
+    #[test]
+    fn test_source_links() {
+        let blocks = markdown::parse(
+            "``` {.text startFrom=7 file=src/a/src/main.rs}\nx\ny\n```\n\n``` {.text .compile_fail}\nz\n```\n\n\
+             ``` {.text startFrom=3 endAt=9 file=b.rs}\nw\n```\n",
+        );
+        let link = |file: &str, start: usize, end: usize| Some(format!("{}#L{}-L{}", file, start, end));
+        assert_eq!(
+            Renderer::new(&[]).with_source_links(&link).blocks(&blocks),
+            "<pre><code class=\"language-text\">x\ny\n</code></pre>\n\
+             <p class=\"edit-source\"><a href=\"src/a/src/main.rs#L7-L8\" title=\"src/a/src/main.rs, lines 7-8\">Edit source</a></p>\n\
+             <pre class=\"compile-fail\"><code class=\"language-text\">z\n</code></pre>\n\
+             <pre><code class=\"language-text\">w\n</code></pre>\n\
+             <p class=\"edit-source\"><a href=\"b.rs#L3-L9\" title=\"b.rs, lines 3-9\">Edit source</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn test_lists() {
+        assert_eq!(render("- a\n- b\n"), "<ul>\n<li>a</li>\n<li>b</li>\n</ul>\n");
+        assert_eq!(render("3. a\n\n4. b\n"), "<ol start=\"3\">\n<li>\n<p>a</p>\n</li>\n<li>\n<p>b</p>\n</li>\n</ol>\n");
+    }
+
+    #[test]
+    fn test_toc() {
+        let blocks = markdown::parse("# A\n## B\n#### Too deep\n## C\n# D\n");
+        let headings = toc::headings(&blocks, "chapter", &mut Anchors::default());
+        assert_eq!(
+            toc(&toc::shallow(&headings, toc::DEPTH), &|h| format!("#{}", h.anchor)),
+            "<nav id=\"TOC\" role=\"doc-toc\">\n<ul>\n\
+             <li><a href=\"#a\">A</a>\n<ul>\n<li><a href=\"#b\">B</a></li>\n<li><a href=\"#c\">C</a></li>\n</ul></li>\n\
+             <li><a href=\"#d\">D</a></li>\n</ul>\n</nav>\n"
+        );
+    }
+}
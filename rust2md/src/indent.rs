@@ -0,0 +1,174 @@
+//
+// Indentation is measured in columns, not bytes: a tab advances to the
+// next multiple of the tab width, and every other character takes the
+// columns a terminal gives it, however many bytes it is made of. Most
+// characters take one; East Asian wide characters (such as the
+// ideographic space) and emoji take two; combining marks and other
+// zero-width characters take none. Grapheme clusters are not
+// segmented, so a cluster is as wide as its characters put together.
+//
+
+// Zero-width characters: combining marks, joiners and the like
+const ZERO_WIDTH: &[(u32, u32)] = &[
+    (0x0300, 0x036F),
+    (0x0483, 0x0489),
+    (0x0591, 0x05BD),
+    (0x0610, 0x061A),
+    (0x064B, 0x065F),
+    (0x0E31, 0x0E31),
+    (0x0E34, 0x0E3A),
+    (0x0E47, 0x0E4E),
+    (0x1AB0, 0x1AFF),
+    (0x1DC0, 0x1DFF),
+    (0x200B, 0x200F),
+    (0x202A, 0x202E),
+    (0x2060, 0x2064),
+    (0x20D0, 0x20FF),
+    (0xFE00, 0xFE0F),
+    (0xFE20, 0xFE2F),
+    (0xFEFF, 0xFEFF),
+    (0xE0100, 0xE01EF),
+];
+
+// Characters two columns wide: East Asian wide and full-width ones, and emoji
+const WIDE: &[(u32, u32)] = &[
+    (0x1100, 0x115F),
+    (0x231A, 0x231B),
+    (0x2329, 0x232A),
+    (0x23E9, 0x23EC),
+    (0x25FD, 0x25FE),
+    (0x2614, 0x2615),
+    (0x2648, 0x2653),
+    (0x26AA, 0x26AB),
+    (0x26BD, 0x26BE),
+    (0x26C4, 0x26C5),
+    (0x2705, 0x2705),
+    (0x270A, 0x270B),
+    (0x2728, 0x2728),
+    (0x274C, 0x274C),
+    (0x2753, 0x2757),
+    (0x2795, 0x2797),
+    (0x2B1B, 0x2B1C),
+    (0x2B50, 0x2B55),
+    (0x2E80, 0x303E),
+    (0x3041, 0x33FF),
+    (0x3400, 0x4DBF),
+    (0x4E00, 0x9FFF),
+    (0xA000, 0xA4CF),
+    (0xA960, 0xA97F),
+    (0xAC00, 0xD7A3),
+    (0xF900, 0xFAFF),
+    (0xFE10, 0xFE19),
+    (0xFE30, 0xFE6F),
+    (0xFF00, 0xFF60),
+    (0xFFE0, 0xFFE6),
+    (0x1F004, 0x1F004),
+    (0x1F200, 0x1F251),
+    (0x1F300, 0x1F64F),
+    (0x1F680, 0x1F6FF),
+    (0x1F900, 0x1F9FF),
+    (0x1FA70, 0x1FAFF),
+    (0x20000, 0x2FFFD),
+    (0x30000, 0x3FFFD),
+];
+
+// The column reached after `text`, starting from column 0
+pub fn width(text: &str, tab_width: usize) -> usize {
+    text.chars().fold(0, |column, c| advance(column, c, tab_width))
+}
+
+fn advance(column: usize, c: char, tab_width: usize) -> usize {
+    if c == '\t' && tab_width > 0 {
+        column + tab_width - column % tab_width
+    } else {
+        column + char_width(c)
+    }
+}
+
+// The columns `c` takes, other than a tab
+pub fn char_width(c: char) -> usize {
+    let within = |ranges: &[(u32, u32)]| {
+        ranges.binary_search_by(|&(first, last)| {
+            if last < c as u32 {
+                std::cmp::Ordering::Less
+            } else if first > c as u32 {
+                std::cmp::Ordering::Greater
+            } else {
+                std::cmp::Ordering::Equal
+            }
+        })
+        .is_ok()
+    };
+    if within(ZERO_WIDTH) {
+        0
+    } else if within(WIDE) {
+        2
+    } else {
+        1
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dedented {
+    // The line had at least `column` columns of indentation
+    Line(String),
+    // The line holds nothing but whitespace
+    Blank,
+    // The text started before `column`; it is returned without any
+    // indentation at all
+    Short(String),
+}
+
+//
+// Removes `column` columns of indentation from `line`. Whatever
+// indentation is left is expanded to spaces, so a tab that straddles
+// `column` leaves behind the spaces it would have taken past it.
+//
+pub fn dedent(line: &str, column: usize, tab_width: usize) -> Dedented {
+    let mut width = 0;
+    for (i, c) in line.char_indices() {
+        if !c.is_whitespace() {
+            if width < column {
+                return Dedented::Short(line[i..].to_string());
+            }
+            return Dedented::Line(format!("{}{}", " ".repeat(width - column), &line[i..]));
+        }
+        width = advance(width, c, tab_width);
+    }
+    if width > column {
+        return Dedented::Line(" ".repeat(width - column));
+    }
+    Dedented::Blank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width() {
+        assert_eq!(width("    ", 4), 4);
+        assert_eq!(width("\t", 4), 4);
+        assert_eq!(width("  \t", 4), 4);
+        assert_eq!(width("  \t", 8), 8);
+        // Wide characters take two columns, combining marks none
+        assert_eq!(width("é\u{3000}", 4), 3);
+        assert_eq!(width("e\u{301}x", 4), 2);
+        assert_eq!(width("日本\t", 4), 8);
+        assert_eq!(width("😊", 4), 2);
+    }
+
+    #[test]
+    fn test_dedent() {
+        assert_eq!(dedent("        f();", 4, 4), Dedented::Line("    f();".to_string()));
+        assert_eq!(dedent("\tf();", 4, 4), Dedented::Line("f();".to_string()));
+        assert_eq!(dedent("\t\tf();", 4, 4), Dedented::Line("    f();".to_string()));
+        assert_eq!(dedent("\tf();", 2, 4), Dedented::Line("  f();".to_string()));
+        assert_eq!(dedent("  f();", 4, 4), Dedented::Short("f();".to_string()));
+        assert_eq!(dedent("\u{a0}\u{a0}é", 2, 4), Dedented::Line("é".to_string()));
+        assert_eq!(dedent("\u{3000}\u{3000}f();", 2, 4), Dedented::Line("  f();".to_string()));
+        assert_eq!(dedent("  ", 4, 4), Dedented::Blank);
+        assert_eq!(dedent("      ", 4, 4), Dedented::Line("  ".to_string()));
+        assert_eq!(dedent("f();", 0, 4), Dedented::Line("f();".to_string()));
+    }
+}
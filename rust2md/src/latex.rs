@@ -0,0 +1,299 @@
+//
+// Renders parsed markdown as LaTeX, for a printed copy of the book.
+// Top-level headings become chapters and the ones below them sections;
+// code goes into `listings` environments, highlighted as Rust, and
+// inline code into `\texttt`. Of raw HTML, only the text is kept,
+// with its entities and `<br>` line breaks: tags have no place on
+// paper, and neither do test badges. An HTML block with no text, such
+// as an image, is left out, and the renderer says so (see `dropped`).
+// The document is meant for `xelatex` or `lualatex`, which cope with
+// any character the book uses; `pdflatex` does too, short of emoji.
+//
+use crate::highlight;
+use crate::manifest::Metadata;
+use crate::markdown::{self, Block, Inline};
+use crate::toc::Heading;
+use crate::xhtml;
+
+// Code in listings steps out to LaTeX between these, for characters
+// `listings` does not know what to do with
+const ESCAPE_START: &str = "(*@";
+const ESCAPE_END: &str = "@*)";
+
+//
+// Renders the blocks of one chapter. Headings get labels with the
+// anchors worked out for the table of contents, in the same order, so
+// that links to them still lead somewhere.
+//
+pub struct Renderer<'a> {
+    headings: std::slice::Iter<'a, Heading>,
+    dropped: Vec<String>,
+}
+
+impl<'a> Renderer<'a> {
+    pub fn new(headings: &'a [Heading]) -> Renderer<'a> {
+        Renderer { headings: headings.iter(), dropped: Vec::new() }
+    }
+
+    // The HTML blocks left out so far, by their first line
+    pub fn dropped(&self) -> &[String] {
+        &self.dropped
+    }
+
+    pub fn blocks(&mut self, blocks: &[Block]) -> String {
+        let mut out = String::new();
+        for block in blocks {
+            self.block(block, &mut out);
+        }
+        out
+    }
+
+    fn block(&mut self, block: &Block, out: &mut String) {
+        match block {
+            Block::Heading { level, text } => {
+                let command = ["chapter", "section", "subsection", "subsubsection", "paragraph", "subparagraph"][level.clamp(&1, &6) - 1];
+                let label = self.headings.next().map(|h| format!("\\label{{{}}}", h.anchor)).unwrap_or_default();
+                out.push_str(&format!("\\{}{{{}}}{}\n\n", command, inlines(text), label));
+            }
+            Block::Paragraph(text) => out.push_str(&format!("{}\n\n", inlines(text))),
+            Block::Code { info, text } => {
+                let mut options = Vec::new();
+                if markdown::language(info) == Some("rust") {
+                    options.push("language=Rust");
+                }
+                if info.split_whitespace().any(|word| word.trim_matches(['{', '}']) == ".compile_fail") {
+                    options.push("title={Does not compile}");
+                }
+                let options = if options.is_empty() { String::new() } else { format!("[{}]", options.join(", ")) };
+                out.push_str(&format!("\\begin{{lstlisting}}{}\n{}\\end{{lstlisting}}\n\n", options, code(text)));
+            }
+            Block::Quote(blocks) => {
+                out.push_str("\\begin{quote}\n");
+                out.push_str(&self.blocks(blocks));
+                out.push_str("\\end{quote}\n\n");
+            }
+            Block::List { start, items, .. } => {
+                let environment = if start.is_some() { "enumerate" } else { "itemize" };
+                out.push_str(&format!("\\begin{{{}}}\n", environment));
+                if let Some(n) = start.filter(|&n| n != 1) {
+                    out.push_str(&format!("\\setcounter{{enumi}}{{{}}}\n", n.saturating_sub(1)));
+                }
+                for item in items {
+                    out.push_str("\\item ");
+                    out.push_str(self.blocks(item).trim_end());
+                    out.push('\n');
+                }
+                out.push_str(&format!("\\end{{{}}}\n\n", environment));
+            }
+            Block::Rule => out.push_str("\\par\\noindent\\rule{\\textwidth}{0.4pt}\n\n"),
+            // Test badges are for the screen
+            Block::Html(html) if html.starts_with("<p class=\"test ") => {}
+            Block::Html(html) => {
+                let text = self::html(html);
+                if !text.trim().is_empty() {
+                    out.push_str(&format!("{}\n\n", text.trim()));
+                } else if !html_tags(html).iter().all(|tag| TEXT_TAGS.contains(&tag.as_str())) {
+                    self.dropped.push(html.lines().next().unwrap_or_default().to_string());
+                }
+            }
+        }
+    }
+}
+
+pub fn inlines(inlines: &[Inline]) -> String {
+    let mut out = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) => out.push_str(&escape(text)),
+            Inline::Code(code) => out.push_str(&format!("\\texttt{{{}}}", escape(code))),
+            Inline::Emphasis(inner) => out.push_str(&format!("\\emph{{{}}}", self::inlines(inner))),
+            Inline::Strong(inner) => out.push_str(&format!("\\textbf{{{}}}", self::inlines(inner))),
+            // Links within the book go to the heading's label; links to
+            // files that are not in it, such as drafts, lead nowhere on paper
+            Inline::Link { url, text, .. } => match url.strip_prefix('#') {
+                Some(anchor) => out.push_str(&format!("\\hyperref[{}]{{{}}}", anchor, self::inlines(text))),
+                None if url.contains(':') => {
+                    let url = url.replace('\\', "/").replace('#', "\\#").replace('%', "\\%");
+                    out.push_str(&format!("\\href{{{}}}{{{}}}", url, self::inlines(text)))
+                }
+                None => out.push_str(&self::inlines(text)),
+            },
+            Inline::Image { alt, .. } => out.push_str(&format!("\\emph{{{}}}", self::inlines(alt))),
+            Inline::Html(html) => out.push_str(&self::html(html)),
+            Inline::SoftBreak => out.push('\n'),
+            Inline::HardBreak => out.push_str("\\\\\n"),
+        }
+    }
+    out
+}
+
+//
+// The text of raw HTML: tags go, but for `<br>`, which breaks the line,
+// and so do comments, scripts and styles. Entities become what they
+// stand for, or, when unknown, show as they were written.
+//
+fn html(html: &str) -> String {
+    let mut out = String::new();
+    let mut rest = html;
+    while let Some(at) = rest.find(['<', '&']) {
+        out.push_str(&escape(&rest[..at]));
+        rest = &rest[at..];
+        if let Some(entity) = rest.strip_prefix('&') {
+            let end = entity.find(';').filter(|&end| end <= 32);
+            match end.and_then(|end| xhtml::character(&entity[..end]).map(|c| (c, end))) {
+                Some((c, end)) => {
+                    out.push_str(&if c == '\u{a0}' { String::from("~") } else { escape(&c.to_string()) });
+                    rest = &entity[end + 1..];
+                }
+                None => {
+                    out.push_str(&escape("&"));
+                    rest = entity;
+                }
+            }
+            continue;
+        }
+        let tag = tag_name(&rest[1..]);
+        let end = match tag.as_deref() {
+            _ if rest.starts_with("<!--") => rest.find("-->").map(|end| end + 3),
+            Some(tag @ ("script" | "style")) => rest.find(&format!("</{}", tag)).and_then(|end| rest[end..].find('>').map(|n| end + n + 1)),
+            Some(_) => rest.find('>').map(|end| end + 1),
+            None => None,
+        };
+        match end {
+            Some(end) => {
+                if tag.as_deref() == Some("br") && !rest[1..].starts_with('/') {
+                    out.push_str("\\\\\n");
+                }
+                rest = &rest[end..];
+            }
+            None => {
+                out.push_str(&escape("<"));
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(&escape(rest));
+    out
+}
+
+// Tags that only hold text, and lose nothing but looks when they go
+const TEXT_TAGS: [&str; 20] = ["a", "b", "br", "code", "details", "div", "em", "i", "kbd", "mark", "p", "s", "section", "small", "span", "strong", "sub", "summary", "sup", "u"];
+
+// The names of the tags in raw HTML, closing ones included
+fn html_tags(html: &str) -> Vec<String> {
+    html.split('<').skip(1).filter_map(tag_name).collect()
+}
+
+// The name of the tag that follows a `<`, lower-cased
+fn tag_name(tag: &str) -> Option<String> {
+    let name: String = tag.strip_prefix('/').unwrap_or(tag).chars().take_while(|c| c.is_ascii_alphanumeric()).collect();
+    name.starts_with(|c: char| c.is_ascii_alphabetic()).then(|| name.to_ascii_lowercase())
+}
+
+// Text with the characters LaTeX gives a meaning to made plain
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => out.push_str("\\textbackslash{}"),
+            '{' | '}' | '#' | '$' | '%' | '&' | '_' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '~' => out.push_str("\\textasciitilde{}"),
+            '^' => out.push_str("\\textasciicircum{}"),
+            '<' => out.push_str("\\textless{}"),
+            '>' => out.push_str("\\textgreater{}"),
+            '|' => out.push_str("\\textbar{}"),
+            '`' => out.push_str("\\textasciigrave{}"),
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+// Code as it goes in a listing, with anything beyond ASCII stepping out of it
+fn code(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            out.push_str(&format!("{}{}{}", ESCAPE_START, c, ESCAPE_END));
+        }
+    }
+    out
+}
+
+//
+// A whole document: the metadata as its title, a table of contents,
+// then the body
+//
+pub fn document(metadata: &Metadata, body: &str) -> String {
+    let mut out = String::from("\\documentclass[11pt]{book}\n\\usepackage{iftex}\n\\ifPDFTeX\n");
+    out.push_str("  \\usepackage[T1]{fontenc}\n  \\usepackage[utf8]{inputenc}\n  \\usepackage{lmodern}\n\\else\n");
+    out.push_str("  \\usepackage{fontspec}\n\\fi\n\\usepackage{xcolor}\n\\usepackage{listings}\n");
+    out.push_str("\\usepackage[hidelinks]{hyperref}\n\n");
+    out.push_str(&format!("\\lstdefinelanguage{{Rust}}{{\n  morekeywords={{{}}},\n", highlight::KEYWORDS.join(",")));
+    out.push_str("  morecomment=[l]{//},\n  morecomment=[s]{/*}{*/},\n  morestring=[b]\",\n  sensitive=true,\n}\n");
+    out.push_str("\\lstset{\n  basicstyle=\\ttfamily\\small,\n  keywordstyle=\\color{teal}\\bfseries,\n");
+    out.push_str("  commentstyle=\\color{gray}\\itshape,\n  stringstyle=\\color{blue!60!black},\n");
+    out.push_str("  columns=fullflexible,\n  keepspaces=true,\n  breaklines=true,\n  frame=single,\n");
+    out.push_str(&format!("  escapeinside={{{}}}{{{}}},\n}}\n\n", ESCAPE_START, ESCAPE_END));
+    let field = |value: &Option<String>| value.as_deref().map(escape).unwrap_or_default();
+    out.push_str(&format!("\\title{{{}}}\n\\author{{{}}}\n\\date{{{}}}\n\n", field(&metadata.title), field(&metadata.author), field(&metadata.date)));
+    out.push_str("\\begin{document}\n\n");
+    if metadata.title.is_some() {
+        out.push_str("\\maketitle\n");
+    }
+    out.push_str("\\tableofcontents\n\n");
+    out.push_str(body);
+    out.push_str("\\end{document}\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toc::{self, Anchors};
+
+    fn render(text: &str) -> String {
+        let blocks = markdown::parse(text);
+        let headings = toc::headings(&blocks, "a", &mut Anchors::default());
+        Renderer::new(&headings).blocks(&blocks)
+    }
+
+    #[test]
+    fn test_blocks() {
+        assert_eq!(
+            render("# Data & Types\n\n## Slices\n\nA `&s[0..2]` *value* in [[x]] [here](#slices).\n\n``` rust\nlet c = '😊';\n```\n\n<p>badge</p>\n"),
+            "\\chapter{Data \\& Types}\\label{data-types}\n\n\
+             \\section{Slices}\\label{slices}\n\n\
+             A \\texttt{\\&s[0..2]} \\emph{value} in [[x]] \\hyperref[slices]{here}.\n\n\
+             \\begin{lstlisting}[language=Rust]\nlet c = '(*@😊@*)';\n\\end{lstlisting}\n\n\
+             badge\n\n"
+        );
+    }
+
+    #[test]
+    fn test_html() {
+        let blocks = markdown::parse(
+            "One&nbsp;two<br>three &amp; <b>bold</b> &#233; &bogus; a < b\n\n\
+             <details>\n<summary>Helper &amp; co</summary>\n\n\
+             <p class=\"test passed\" title=\"t\">✔ passed</p>\n\n\
+             <img src=\"a.png\">\n\n<script>\nlet x = 1;\n</script>\n\n</details>\n",
+        );
+        let mut renderer = Renderer::new(&[]);
+        assert_eq!(
+            renderer.blocks(&blocks),
+            "One~two\\\\\nthree \\& bold é \\&bogus; a \\textless{} b\n\n\
+             Helper \\& co\n\n"
+        );
+        assert_eq!(renderer.dropped(), ["<img src=\"a.png\">", "<script>"]);
+    }
+
+    #[test]
+    fn test_escape() {
+        assert_eq!(escape("a_b {c} 50% #1 $x ~^ \\ <T> |"), "a\\_b \\{c\\} 50\\% \\#1 \\$x \\textasciitilde{}\\textasciicircum{} \\textbackslash{} \\textless{}T\\textgreater{} \\textbar{}");
+    }
+}
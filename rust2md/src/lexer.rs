@@ -0,0 +1,476 @@
+//
+// A small Rust tokenizer, good enough to tell comments from code, and
+// to highlight snippets in the HTML output. It works a line at a time,
+// carrying block comments and string literals over from one line to
+// the next, so that `//` inside a string, or `"` inside a comment, is
+// never mistaken for anything else.
+//
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Whitespace,
+    Ident,
+    Lifetime,
+    Number,
+    // Strings, byte strings and C strings, escapes included
+    Str,
+    // r"..." and r#"..."#, and their byte and C string forms
+    RawStr,
+    Char,
+    Punct,
+    // `// ...` and `//// ...`
+    LineComment,
+    // `/// ...` documents the next item, `//! ...` the enclosing one
+    OuterDoc,
+    InnerDoc,
+    // `/* ... */`, possibly nested, possibly spanning several lines
+    BlockComment,
+    // `/** ... */` and `/*! ... */`
+    OuterBlockDoc,
+    InnerBlockDoc,
+}
+
+impl TokenKind {
+    pub fn is_comment(self) -> bool {
+        matches!(
+            self,
+            TokenKind::LineComment
+                | TokenKind::OuterDoc
+                | TokenKind::InnerDoc
+                | TokenKind::BlockComment
+                | TokenKind::OuterBlockDoc
+                | TokenKind::InnerBlockDoc
+        )
+    }
+}
+
+//
+// A token, as a byte range of the line it was found in
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Token {
+    pub fn text<'a>(&self, line: &'a str) -> &'a str {
+        &line[self.start..self.end]
+    }
+}
+
+//
+// What was left open at the end of the previous line
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Mode {
+    #[default]
+    Normal,
+    BlockComment { depth: usize, kind: TokenKind },
+    Str,
+    RawStr { hashes: usize },
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Lexer {
+    mode: Mode,
+}
+
+impl Lexer {
+    pub fn new() -> Lexer {
+        Lexer::default()
+    }
+
+    // Whether the last line ended inside a block comment
+    pub fn in_block_comment(&self) -> bool {
+        matches!(self.mode, Mode::BlockComment { .. })
+    }
+
+    // What the last line left open, if anything
+    pub fn open(&self) -> Option<&'static str> {
+        match self.mode {
+            Mode::Normal => None,
+            Mode::BlockComment { .. } => Some("block comment"),
+            Mode::Str => Some("string"),
+            Mode::RawStr { .. } => Some("raw string"),
+        }
+    }
+
+    pub fn line(&mut self, line: &str) -> Vec<Token> {
+        let bytes = line.as_bytes();
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+
+        // Finish whatever the previous line left open
+        match self.mode {
+            Mode::Normal => {}
+            Mode::BlockComment { depth, kind } => {
+                pos = self.block_comment(bytes, 0, depth, kind);
+                tokens.push(Token { kind, start: 0, end: pos });
+            }
+            Mode::Str => {
+                pos = self.string(bytes, 0);
+                tokens.push(Token { kind: TokenKind::Str, start: 0, end: pos });
+            }
+            Mode::RawStr { hashes } => {
+                pos = self.raw_string(bytes, 0, hashes);
+                tokens.push(Token { kind: TokenKind::RawStr, start: 0, end: pos });
+            }
+        }
+
+        while pos < bytes.len() {
+            let start = pos;
+            let c = bytes[pos];
+            let next = bytes.get(pos + 1).copied();
+            let kind = match c {
+                b' ' | b'\t' | b'\r' | b'\n' => {
+                    while pos < bytes.len() && matches!(bytes[pos], b' ' | b'\t' | b'\r' | b'\n') {
+                        pos += 1;
+                    }
+                    TokenKind::Whitespace
+                }
+                b'/' if next == Some(b'/') => {
+                    let kind = match (bytes.get(pos + 2), bytes.get(pos + 3)) {
+                        (Some(b'!'), _) => TokenKind::InnerDoc,
+                        (Some(b'/'), Some(b'/')) => TokenKind::LineComment,
+                        (Some(b'/'), _) => TokenKind::OuterDoc,
+                        _ => TokenKind::LineComment,
+                    };
+                    pos = bytes.len();
+                    kind
+                }
+                b'/' if next == Some(b'*') => {
+                    let kind = match (bytes.get(pos + 2), bytes.get(pos + 3)) {
+                        (Some(b'!'), _) => TokenKind::InnerBlockDoc,
+                        // `/**/` and `/***` are not doc comments
+                        (Some(b'*'), Some(b'/')) | (Some(b'*'), Some(b'*')) => TokenKind::BlockComment,
+                        (Some(b'*'), _) => TokenKind::OuterBlockDoc,
+                        _ => TokenKind::BlockComment,
+                    };
+                    pos = self.block_comment(bytes, pos + 2, 1, kind);
+                    kind
+                }
+                b'"' => {
+                    pos = self.string(bytes, pos + 1);
+                    TokenKind::Str
+                }
+                b'r' | b'b' | b'c' if raw_string_start(bytes, pos).is_some() => {
+                    let (quote, hashes) = raw_string_start(bytes, pos).unwrap();
+                    pos = self.raw_string(bytes, quote + 1, hashes);
+                    TokenKind::RawStr
+                }
+                b'b' | b'c' if next == Some(b'"') => {
+                    pos = self.string(bytes, pos + 2);
+                    TokenKind::Str
+                }
+                b'b' if next == Some(b'\'') => {
+                    pos = char_literal(bytes, pos + 1).unwrap_or(pos + 2);
+                    TokenKind::Char
+                }
+                b'\'' => match char_literal(bytes, pos) {
+                    Some(end) => {
+                        pos = end;
+                        TokenKind::Char
+                    }
+                    None => {
+                        pos += 1;
+                        while pos < bytes.len() && is_ident(bytes[pos]) {
+                            pos += 1;
+                        }
+                        TokenKind::Lifetime
+                    }
+                },
+                c if c.is_ascii_digit() => {
+                    while pos < bytes.len() && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_' || is_decimal_point(bytes, pos)) {
+                        pos += 1;
+                    }
+                    TokenKind::Number
+                }
+                c if is_ident(c) || c >= 0x80 => {
+                    while pos < bytes.len() && (is_ident(bytes[pos]) || bytes[pos] >= 0x80) {
+                        pos += 1;
+                    }
+                    TokenKind::Ident
+                }
+                _ => {
+                    pos += 1;
+                    TokenKind::Punct
+                }
+            };
+            tokens.push(Token { kind, start, end: pos });
+        }
+        tokens
+    }
+
+    //
+    // Scans a block comment from `pos`, where `depth` comments are
+    // open. Returns where it ends, or the end of the line if it does
+    // not, in which case the comment carries on into the next line.
+    //
+    fn block_comment(&mut self, bytes: &[u8], mut pos: usize, mut depth: usize, kind: TokenKind) -> usize {
+        while pos < bytes.len() {
+            if bytes[pos] == b'/' && bytes.get(pos + 1) == Some(&b'*') {
+                depth += 1;
+                pos += 2;
+            } else if bytes[pos] == b'*' && bytes.get(pos + 1) == Some(&b'/') {
+                depth -= 1;
+                pos += 2;
+                if depth == 0 {
+                    self.mode = Mode::Normal;
+                    return pos;
+                }
+            } else {
+                pos += 1;
+            }
+        }
+        self.mode = Mode::BlockComment { depth, kind };
+        pos
+    }
+
+    fn string(&mut self, bytes: &[u8], mut pos: usize) -> usize {
+        while pos < bytes.len() {
+            match bytes[pos] {
+                b'\\' => pos += 2,
+                b'"' => {
+                    self.mode = Mode::Normal;
+                    return pos + 1;
+                }
+                _ => pos += 1,
+            }
+        }
+        self.mode = Mode::Str;
+        bytes.len()
+    }
+
+    fn raw_string(&mut self, bytes: &[u8], mut pos: usize, hashes: usize) -> usize {
+        while pos < bytes.len() {
+            if bytes[pos] == b'"' && bytes[pos + 1..].iter().take(hashes).filter(|&&b| b == b'#').count() == hashes {
+                self.mode = Mode::Normal;
+                return pos + 1 + hashes;
+            }
+            pos += 1;
+        }
+        self.mode = Mode::RawStr { hashes };
+        bytes.len()
+    }
+}
+
+fn is_ident(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+// `1.5` but not `1..5` nor `x.0.len()`
+fn is_decimal_point(bytes: &[u8], pos: usize) -> bool {
+    bytes[pos] == b'.' && bytes.get(pos + 1).is_some_and(|c| c.is_ascii_digit())
+}
+
+//
+// If a raw string (`r"`, `r#"`, `br"`, `cr#"`...) starts at `pos`,
+// the position of its opening quote and its number of hashes
+//
+fn raw_string_start(bytes: &[u8], pos: usize) -> Option<(usize, usize)> {
+    if pos > 0 && is_ident(bytes[pos - 1]) {
+        return None;
+    }
+    let mut i = pos;
+    if bytes[i] == b'b' || bytes[i] == b'c' {
+        i += 1;
+    }
+    if bytes.get(i) != Some(&b'r') {
+        return None;
+    }
+    i += 1;
+    let hashes = bytes[i..].iter().take_while(|&&b| b == b'#').count();
+    if bytes.get(i + hashes) == Some(&b'"') {
+        Some((i + hashes, hashes))
+    } else {
+        None
+    }
+}
+
+//
+// If a character literal starts at the quote in `pos`, where it ends.
+// `'a'`, `'\n'` and `'\u{1F600}'` are characters, `'a` is a lifetime.
+//
+fn char_literal(bytes: &[u8], pos: usize) -> Option<usize> {
+    match bytes.get(pos + 1)? {
+        b'\\' => {
+            let close = bytes.get(pos + 3..)?.iter().position(|&b| b == b'\'')?;
+            Some(pos + 3 + close + 1)
+        }
+        _ => {
+            // The character itself may take several bytes
+            let len = std::str::from_utf8(&bytes[pos + 1..]).ok()?.chars().next()?.len_utf8();
+            if bytes.get(pos + 1 + len) == Some(&b'\'') {
+                Some(pos + 2 + len)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+//
+// What a whole line amounts to, as far as rust2md is concerned
+//
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineKind {
+    Blank,
+    // A line holding nothing but a `//`, `///` or `//!` comment
+    Comment { kind: TokenKind, column: usize },
+    // A line holding nothing but block comments (or part of one)
+    BlockComment,
+    Code,
+}
+
+pub fn classify(tokens: &[Token]) -> LineKind {
+    let mut significant = tokens.iter().filter(|t| t.kind != TokenKind::Whitespace);
+    let first = match significant.next() {
+        Some(token) => token,
+        None => return LineKind::Blank,
+    };
+    match first.kind {
+        TokenKind::LineComment | TokenKind::OuterDoc | TokenKind::InnerDoc => {
+            LineKind::Comment { kind: first.kind, column: first.start }
+        }
+        kind if kind.is_comment() => {
+            if significant.all(|t| t.kind.is_comment()) {
+                LineKind::BlockComment
+            } else {
+                LineKind::Code
+            }
+        }
+        _ => LineKind::Code,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(lexer: &mut Lexer, line: &str) -> Vec<(TokenKind, String)> {
+        lexer
+            .line(line)
+            .into_iter()
+            .filter(|t| t.kind != TokenKind::Whitespace)
+            .map(|t| (t.kind, t.text(line).to_string()))
+            .collect()
+    }
+
+    fn kind_of(line: &str) -> LineKind {
+        classify(&Lexer::new().line(line))
+    }
+
+    #[test]
+    fn test_comment_lines() {
+        assert_eq!(kind_of("    // text"), LineKind::Comment { kind: TokenKind::LineComment, column: 4 });
+        assert_eq!(kind_of("/// item"), LineKind::Comment { kind: TokenKind::OuterDoc, column: 0 });
+        assert_eq!(kind_of("  //! crate"), LineKind::Comment { kind: TokenKind::InnerDoc, column: 2 });
+        assert_eq!(kind_of("//// rule"), LineKind::Comment { kind: TokenKind::LineComment, column: 0 });
+        assert_eq!(kind_of("   "), LineKind::Blank);
+    }
+
+    #[test]
+    fn test_trailing_comment_is_code() {
+        assert_eq!(kind_of("let url = \"http://x\"; // note"), LineKind::Code);
+        assert_eq!(kind_of("assert_eq!(usize::MIN, 0); /* Arch dependant */"), LineKind::Code);
+    }
+
+    #[test]
+    fn test_slashes_in_strings() {
+        let mut lexer = Lexer::new();
+        assert_eq!(
+            kinds(&mut lexer, r#"let s = "// no";"#),
+            vec![
+                (TokenKind::Ident, "let".to_string()),
+                (TokenKind::Ident, "s".to_string()),
+                (TokenKind::Punct, "=".to_string()),
+                (TokenKind::Str, "\"// no\"".to_string()),
+                (TokenKind::Punct, ";".to_string()),
+            ]
+        );
+        assert_eq!(kind_of(r#""\" // still a string";"#), LineKind::Code);
+    }
+
+    #[test]
+    fn test_multi_line_string() {
+        let mut lexer = Lexer::new();
+        assert_eq!(classify(&lexer.line("let s = \"first")), LineKind::Code);
+        assert_eq!(classify(&lexer.line("// not a comment")), LineKind::Code);
+        assert_eq!(classify(&lexer.line("end\";")), LineKind::Code);
+        assert_eq!(classify(&lexer.line("// a comment")), LineKind::Comment { kind: TokenKind::LineComment, column: 0 });
+    }
+
+    #[test]
+    fn test_raw_strings() {
+        let mut lexer = Lexer::new();
+        assert_eq!(kinds(&mut lexer, r###"r#"a "quoted" // b"#"###), vec![(TokenKind::RawStr, r###"r#"a "quoted" // b"#"###.to_string())]);
+        assert_eq!(kinds(&mut lexer, r#"br"\d""#), vec![(TokenKind::RawStr, r#"br"\d""#.to_string())]);
+        assert_eq!(classify(&lexer.line("let s = r##\"")), LineKind::Code);
+        assert_eq!(classify(&lexer.line("// inside \"# still")), LineKind::Code);
+        assert_eq!(classify(&lexer.line("\"##;")), LineKind::Code);
+        assert_eq!(classify(&lexer.line("// out")), LineKind::Comment { kind: TokenKind::LineComment, column: 0 });
+        // An identifier ending in `r` is not a raw string
+        assert_eq!(kinds(&mut lexer, "for\"x\"")[0], (TokenKind::Ident, "for".to_string()));
+    }
+
+    #[test]
+    fn test_chars_and_lifetimes() {
+        let mut lexer = Lexer::new();
+        assert_eq!(
+            kinds(&mut lexer, r"'/' '\'' 'a 'é' '\u{1F600}' 'static"),
+            vec![
+                (TokenKind::Char, "'/'".to_string()),
+                (TokenKind::Char, r"'\''".to_string()),
+                (TokenKind::Lifetime, "'a".to_string()),
+                (TokenKind::Char, "'é'".to_string()),
+                (TokenKind::Char, r"'\u{1F600}'".to_string()),
+                (TokenKind::Lifetime, "'static".to_string()),
+            ]
+        );
+        assert_eq!(kind_of("let c = '\"'; // quote"), LineKind::Code);
+    }
+
+    #[test]
+    fn test_nested_block_comments() {
+        let mut lexer = Lexer::new();
+        assert_eq!(
+            kinds(&mut lexer, "/* a /* b */ c */ x"),
+            vec![(TokenKind::BlockComment, "/* a /* b */ c */".to_string()), (TokenKind::Ident, "x".to_string())]
+        );
+        assert_eq!(classify(&lexer.line("/* outer /* inner */")), LineKind::BlockComment);
+        assert!(lexer.in_block_comment());
+        assert_eq!(classify(&lexer.line("// still commented")), LineKind::BlockComment);
+        assert_eq!(classify(&lexer.line("*/ let x = 1;")), LineKind::Code);
+        assert!(!lexer.in_block_comment());
+    }
+
+    #[test]
+    fn test_block_doc_comments() {
+        let mut lexer = Lexer::new();
+        assert_eq!(lexer.line("/*! inner */")[0].kind, TokenKind::InnerBlockDoc);
+        assert_eq!(lexer.line("/** outer */")[0].kind, TokenKind::OuterBlockDoc);
+        assert_eq!(lexer.line("/**/")[0].kind, TokenKind::BlockComment);
+        assert_eq!(lexer.line("/*** rule */")[0].kind, TokenKind::BlockComment);
+    }
+
+    #[test]
+    fn test_numbers() {
+        let mut lexer = Lexer::new();
+        assert_eq!(
+            kinds(&mut lexer, "1.5 0..10 t.0 1_000u32"),
+            vec![
+                (TokenKind::Number, "1.5".to_string()),
+                (TokenKind::Number, "0".to_string()),
+                (TokenKind::Punct, ".".to_string()),
+                (TokenKind::Punct, ".".to_string()),
+                (TokenKind::Number, "10".to_string()),
+                (TokenKind::Ident, "t".to_string()),
+                (TokenKind::Punct, ".".to_string()),
+                (TokenKind::Number, "0".to_string()),
+                (TokenKind::Number, "1_000u32".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,576 @@
+//
+// rust2md converts a Rust file to markdown following these rules:
+//
+// 1. Code is ignored until the first comment appears
+// 2. Comments using // (and //!) are interpreted as containing markdown text
+// 3. Comments using /* */ are kept in the code, unless `Options::block_comments` says otherwise
+// 4. Code following comments will be included using the markdown code fence notation
+// 5. Code should start in the last column as the last comment
+//
+// `convert` does the whole job for one file. `parse` stops at the
+// `Document`, for callers that want to render it some other way, or
+// to resolve its includes against other files (see `region`).
+//
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+pub mod book;
+pub mod cache;
+pub mod compile_fail;
+pub mod diagnostics;
+pub mod diff;
+pub mod document;
+pub mod epub;
+pub mod highlight;
+pub mod html;
+pub mod indent;
+pub mod json;
+pub mod latex;
+pub mod lexer;
+pub mod libtest;
+pub mod manifest;
+pub mod markdown;
+pub mod output;
+pub mod parser;
+pub mod region;
+pub mod scaffolding;
+pub mod serve;
+pub mod toc;
+pub mod toml;
+pub mod watch;
+pub mod xhtml;
+pub mod xref;
+
+use diagnostics::Diagnostic;
+use document::{Block, Document};
+use parser::Parser;
+use scaffolding::Scaffolding;
+use xref::Reference;
+
+//
+// What to do with `/* ... */` comments
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BlockComments {
+    // They are code, like everything else that is not a // comment
+    #[default]
+    Keep,
+    // They are left out of the snippets
+    Strip,
+    // `/*! ... */` comments on lines of their own are prose; any
+    // other block comment is kept
+    Prose,
+}
+
+impl BlockComments {
+    pub fn parse(name: &str) -> Option<BlockComments> {
+        match name {
+            "keep" => Some(BlockComments::Keep),
+            "strip" => Some(BlockComments::Strip),
+            "prose" => Some(BlockComments::Prose),
+            _ => None,
+        }
+    }
+}
+
+//
+// What to do with `///` comments. `//!` comments document the chapter
+// and are always prose.
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DocComments {
+    // They stay in the snippet, attached to the item they document
+    #[default]
+    Attach,
+    // They are lifted out of the snippet, as prose describing the item
+    Lift,
+}
+
+impl DocComments {
+    pub fn parse(name: &str) -> Option<DocComments> {
+        match name {
+            "attach" => Some(DocComments::Attach),
+            "lift" => Some(DocComments::Lift),
+            _ => None,
+        }
+    }
+}
+
+//
+// How a fence says what its code is
+//
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Fences {
+    // The language, or pandoc attributes when there is more to say, as
+    // in ``` {.rust .compile_fail startFrom=17}
+    #[default]
+    Pandoc,
+    // The language and the attributes mdBook knows, as in
+    // ``` rust,should_panic
+    MdBook,
+}
+
+impl Fences {
+    pub fn parse(name: &str) -> Option<Fences> {
+        match name {
+            "pandoc" => Some(Fences::Pandoc),
+            "mdbook" => Some(Fences::MdBook),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    // The info string of every code fence
+    pub language: String,
+    pub block_comments: BlockComments,
+    pub doc_comments: DocComments,
+    // How many columns a tab advances to, when measuring indentation
+    pub tab_width: usize,
+    // Whether fences say where their code came from, as in
+    // ``` {.rust startFrom=17 file=src/control_flow/src/main.rs}, with
+    // `endAt=` for the last line when unwrapped tests leave lines out
+    pub source_lines: bool,
+    // The file named by `file=` in those fences
+    pub source: Option<String>,
+    pub test_scaffolding: Scaffolding,
+    pub fences: Fences,
+}
+
+impl Default for Options {
+    fn default() -> Options {
+        Options {
+            language: String::from("rust"),
+            block_comments: BlockComments::default(),
+            doc_comments: DocComments::default(),
+            tab_width: 4,
+            source_lines: false,
+            source: None,
+            test_scaffolding: Scaffolding::default(),
+            fences: Fences::default(),
+        }
+    }
+}
+
+//
+// What a conversion found, for callers that want more than the output
+//
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub lines: usize,
+    pub prose_lines: usize,
+    pub snippets: usize,
+    pub diagnostics: Vec<Diagnostic>,
+    // The `[[...]]` cross-references in the prose, for book mode to resolve
+    pub references: Vec<Reference>,
+}
+
+impl Report {
+    pub fn new(document: &Document) -> Report {
+        let mut report = Report {
+            lines: document.lines,
+            diagnostics: document.diagnostics.clone(),
+            ..Report::default()
+        };
+        for block in &document.blocks {
+            match block {
+                Block::Prose(prose) => {
+                    report.prose_lines += prose.lines.len();
+                    for (n, line) in prose.lines.iter().enumerate() {
+                        for (_, target, label) in xref::find(line) {
+                            report.references.push(Reference { line: prose.first_line + n, target, label });
+                        }
+                    }
+                }
+                Block::Code(_) => report.snippets += 1,
+                Block::Include(_) => {}
+            }
+        }
+        report
+    }
+
+    // Whether the input had anything worth publishing at all
+    pub fn is_empty(&self) -> bool {
+        self.prose_lines == 0 && self.snippets == 0
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+pub fn parse<R: BufRead>(input: R, options: &Options) -> Result<Document> {
+    let mut parser = Parser::new(options);
+    for line in input.lines() {
+        parser.line(&line?);
+    }
+    Ok(parser.finish())
+}
+
+pub fn convert<R: BufRead, W: Write>(input: R, mut out: W, options: &Options) -> Result<Report> {
+    let mut document = parse(input, options)?;
+    // On its own, a file can only include its own regions
+    let problems = region::include(std::slice::from_mut(&mut document), &[""], &[options.source.as_deref()]);
+    document.diagnostics.extend(problems.into_iter().flatten());
+    document.write_markdown(&mut out, options)?;
+    Ok(Report::new(&document))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_markdown(input: &str) -> String {
+        let mut out = Vec::new();
+        convert(input.as_bytes(), &mut out, &Options::default()).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    //
+    // 1. Code is ignored until the first comment appears
+    //
+    #[test]
+    fn test_code_before_first_comment_is_ignored() {
+        let input = "fn main() {\n}\n// # Title\n";
+        assert_eq!(to_markdown(input), "# Title\n");
+    }
+
+    #[test]
+    fn test_input_without_comments_is_empty() {
+        let mut out = Vec::new();
+        let report = convert("fn main() {\n}\n".as_bytes(), &mut out, &Options::default()).unwrap();
+        assert!(out.is_empty());
+        assert!(report.is_empty());
+        assert_eq!(report.lines, 2);
+    }
+
+    //
+    // 2. Comments using // (and //!) are interpreted as containing markdown text
+    //
+    #[test]
+    fn test_line_comments_are_markdown() {
+        let input = "    //\n    // ## Heading\n    //\n    // Some *text*\n";
+        assert_eq!(to_markdown(input), "\n## Heading\n\nSome *text*\n");
+    }
+
+    #[test]
+    fn test_only_standalone_comments_are_markdown() {
+        let input = "// Text\nlet url = \"http://x\"; // note\nlet s = \"\n// inside a string\n\";\n";
+        assert_eq!(
+            to_markdown(input),
+            "Text\n\n``` rust\nlet url = \"http://x\"; // note\nlet s = \"\n// inside a string\n\";\n```\n\n"
+        );
+    }
+
+    #[test]
+    fn test_inner_doc_comments_are_markdown() {
+        let input = "//! # Chapter\n//!\n//! About it\nlet a = 1;\n";
+        assert_eq!(to_markdown(input), "# Chapter\n\nAbout it\n\n``` rust\nlet a = 1;\n```\n\n");
+    }
+
+    #[test]
+    fn test_outer_doc_comments_stay_with_their_item() {
+        let input = "    // Text\n    /// Adds one\n    fn inc(x: i32) -> i32 { x + 1 }\n    #[test]\n    /// Tests it\n    fn test_inc() {}\n";
+        assert_eq!(
+            to_markdown(input),
+            "Text\n\n``` rust\n/// Adds one\nfn inc(x: i32) -> i32 { x + 1 }\n#[test]\n/// Tests it\nfn test_inc() {}\n```\n\n"
+        );
+    }
+
+    #[test]
+    fn test_outer_doc_comments_can_be_lifted() {
+        let options = Options { doc_comments: DocComments::Lift, ..Options::default() };
+        let input = "// Text\nlet a = 1;\n/// Adds one\nfn inc(x: i32) -> i32 { x + 1 }\n";
+        let mut out = Vec::new();
+        convert(input.as_bytes(), &mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Text\n\n``` rust\nlet a = 1;\n```\n\nAdds one\n\n``` rust\nfn inc(x: i32) -> i32 { x + 1 }\n```\n\n"
+        );
+    }
+
+    //
+    // 3. Comments using /* */ are kept in the code, unless `Options::block_comments` says otherwise
+    //
+    #[test]
+    fn test_block_comments_are_code() {
+        let input = "// Text\nlet x = 1; /* note */\n/* Helper */\n";
+        assert_eq!(
+            to_markdown(input),
+            "Text\n\n``` rust\nlet x = 1; /* note */\n/* Helper */\n```\n\n"
+        );
+    }
+
+    fn to_markdown_with(input: &str, block_comments: BlockComments) -> String {
+        let options = Options { block_comments, ..Options::default() };
+        let mut out = Vec::new();
+        convert(input.as_bytes(), &mut out, &options).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_block_comments_can_be_stripped() {
+        let input = "// Text\n/* Helper */\nfn f() { /* inline */ g(); } /* trailing */\n/* one\n   two */\nlet x = 1;\n// More\n/* Only a comment */\n";
+        assert_eq!(
+            to_markdown_with(input, BlockComments::Strip),
+            "Text\n\n``` rust\nfn f() { g(); }\nlet x = 1;\n```\n\nMore\n"
+        );
+    }
+
+    #[test]
+    fn test_inner_block_doc_comments_can_be_prose() {
+        let input = "    // Text\n    let a = 1;\n    /*!\n     * ## Heading\n     *\n     * Body\n     */\n    let b = 2;\n    /* kept */\n";
+        assert_eq!(
+            to_markdown_with(input, BlockComments::Prose),
+            "Text\n\n``` rust\nlet a = 1;\n```\n\n## Heading\n\nBody\n\n``` rust\nlet b = 2;\n/* kept */\n```\n\n"
+        );
+        assert_eq!(
+            to_markdown_with("/*! One line */\nlet a = 1;\n", BlockComments::Prose),
+            "One line\n\n``` rust\nlet a = 1;\n```\n\n"
+        );
+        assert_eq!(
+            to_markdown_with("// Text\n/*! Prose\n*/ let a = 1;\n", BlockComments::Prose),
+            "Text\nProse\n\n``` rust\n   let a = 1;\n```\n\n"
+        );
+    }
+
+    //
+    // 4. Code following comments will be included using the markdown
+    // code fence notation
+    //
+    #[test]
+    fn test_code_after_comments_is_fenced() {
+        let input = "// One\nlet a = 1;\n// Two\nlet b = 2;\n";
+        assert_eq!(
+            to_markdown(input),
+            "One\n\n``` rust\nlet a = 1;\n```\n\nTwo\n\n``` rust\nlet b = 2;\n```\n\n"
+        );
+        let mut out = Vec::new();
+        let report = convert(input.as_bytes(), &mut out, &Options::default()).unwrap();
+        assert_eq!(report.snippets, 2);
+        assert_eq!(report.prose_lines, 2);
+    }
+
+    #[test]
+    fn test_fence_language_is_an_option() {
+        let options = Options { language: String::from("rust,ignore"), ..Options::default() };
+        let mut out = Vec::new();
+        convert("// Text\nlet a = 1;\n".as_bytes(), &mut out, &options).unwrap();
+        assert!(String::from_utf8(out).unwrap().contains("``` rust,ignore\n"));
+    }
+
+    //
+    // 5. Code should start in the last column as the last comment
+    //
+    #[test]
+    fn test_code_is_dedented_to_comment_column() {
+        let input = "    // Text\n    fn f() {\n        g();\n    }\n\n";
+        assert_eq!(
+            to_markdown(input),
+            "Text\n\n``` rust\nfn f() {\n    g();\n}\n\n```\n\n"
+        );
+    }
+
+    #[test]
+    fn test_indentation_is_measured_in_columns() {
+        let input = "\t// Ünïcødé prose\n\tfn f() {\n\t\tlet s = \"é\";\n\t}\n";
+        assert_eq!(to_markdown(input), "Ünïcødé prose\n\n``` rust\nfn f() {\n    let s = \"é\";\n}\n```\n\n");
+        let input = "    // Text\n\tlet a = 1;\n";
+        assert_eq!(to_markdown(input), "Text\n\n``` rust\nlet a = 1;\n```\n\n");
+        let options = Options { tab_width: 8, ..Options::default() };
+        let mut out = Vec::new();
+        convert(input.as_bytes(), &mut out, &options).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Text\n\n``` rust\n    let a = 1;\n```\n\n");
+    }
+
+    #[test]
+    fn test_enclosing_scope_is_not_shown() {
+        let input = "mod tests {\n    // Text\n    fn f() {}\n}\n";
+        let mut out = Vec::new();
+        let report = convert(input.as_bytes(), &mut out, &Options::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Text\n\n``` rust\nfn f() {}\n```\n\n");
+        assert!(report.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_crlf_input() {
+        let input = "\u{feff}// Text\r\n//\r\nlet a = 1;\r\n";
+        assert_eq!(to_markdown(input), "Text\n\n\n``` rust\nlet a = 1;\n```\n\n");
+    }
+
+    #[test]
+    fn test_under_indented_code_is_kept_with_a_warning() {
+        let input = "    // Text\n    fn f() {\n  g();\n    }\n";
+        let mut out = Vec::new();
+        let report = convert(input.as_bytes(), &mut out, &Options::default()).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "Text\n\n``` rust\nfn f() {\ng();\n}\n```\n\n");
+        assert_eq!(report.diagnostics.len(), 1);
+        assert_eq!(report.diagnostics[0].line, 3);
+        assert!(report.diagnostics[0].message.contains("g();"));
+    }
+
+    fn diagnostics_of(input: &str) -> Vec<Diagnostic> {
+        parse(input.as_bytes(), &Options::default()).unwrap().diagnostics
+    }
+
+    #[test]
+    fn test_fence_split_inside_a_function() {
+        let input = "    // Text\n    #[test]\n    fn test_x() {\n        let a = 1;\n        // Oops\n        assert_eq!(a, 1);\n    }\n    // Fine\n    fn g() {}\n";
+        assert_eq!(diagnostics_of(input), vec![Diagnostic::new(5, "fence split inside fn test_x")]);
+    }
+
+    #[test]
+    fn test_heading_level_jumps() {
+        let input = "// # One\n// ## Two\n// #### Four\n// # Back\n// #hashtag\n";
+        assert_eq!(diagnostics_of(input), vec![Diagnostic::new(3, "heading level jumps from ## to ####")]);
+    }
+
+    #[test]
+    fn test_things_never_closed() {
+        assert_eq!(
+            diagnostics_of("// Text\nfn f() {\n    /* open\n    still open\n"),
+            vec![
+                Diagnostic::new(4, "block comment opened at line 3 is never closed"),
+                Diagnostic::new(4, "fn f opened at line 2 is never closed"),
+            ]
+        );
+        assert_eq!(
+            diagnostics_of("// Text\nlet s = \"abc\n// def\n"),
+            vec![Diagnostic::new(3, "string opened at line 2 is never closed")]
+        );
+        assert_eq!(
+            diagnostics_of("// Text\n// region: ship\n//\n// More\n\nstruct Ship;\n"),
+            vec![Diagnostic::new(6, "region 'ship' at line 2 is never ended")]
+        );
+    }
+
+    #[test]
+    fn test_snippets_know_their_source_lines() {
+        let document = parse("// Text\nlet a = 1;\nlet b = 2;\n".as_bytes(), &Options::default()).unwrap();
+        let snippet = document.snippets().next().unwrap();
+        assert_eq!(snippet.first_line, 2);
+        assert_eq!(snippet.last_line(), 3);
+    }
+
+    #[test]
+    fn test_fences_can_say_where_code_came_from() {
+        let options = Options { source_lines: true, source: Some(String::from("src/a b/main.rs")), ..Options::default() };
+        let input = "// Text\nlet a = 1;\nlet b = 2;\n// More\nlet c = 3;\n";
+        let mut out = Vec::new();
+        let document = parse(input.as_bytes(), &options).unwrap();
+        document.write_markdown(&mut out, &options).unwrap();
+        let markdown = String::from_utf8(out).unwrap();
+        assert_eq!(
+            markdown,
+            "Text\n\n``` {.rust startFrom=2 file=\"src/a b/main.rs\"}\nlet a = 1;\nlet b = 2;\n```\n\n\
+             More\n\n``` {.rust startFrom=5 file=\"src/a b/main.rs\"}\nlet c = 3;\n```\n\n"
+        );
+        let lines: Vec<&str> = markdown.lines().collect();
+        for fence in document.fence_lines(&options) {
+            assert!(lines[fence - 1].starts_with("``` {"));
+        }
+    }
+
+    #[test]
+    fn test_stripped_snippets_keep_their_source_lines() {
+        let options = Options { test_scaffolding: Scaffolding::Strip, source_lines: true, source: Some(String::from("sc.rs")), ..Options::default() };
+        let input = "// Text\n#[test]\nfn test_x() {\n    let x = 1;\n    assert_eq!(x, 1);\n}\n// More\n#[test]\nfn test_a() {\n    f();\n}\n\
+                     #[test]\nfn test_b() {\n    g();\n}\n";
+        let mut out = Vec::new();
+        let document = parse(input.as_bytes(), &options).unwrap();
+        document.write_markdown(&mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Text\n\n``` {.rust startFrom=4 file=sc.rs}\nlet x = 1;\nassert_eq!(x, 1);\n```\n\n\
+             More\n\n``` {.rust startFrom=10 endAt=14 file=sc.rs}\nf();\n\ng();\n```\n\n"
+        );
+        let snippets: Vec<(usize, usize)> = document.snippets().map(|snippet| snippet.shown_lines(&options)).collect();
+        assert_eq!(snippets, [(4, 5), (10, 14)]);
+    }
+
+    #[test]
+    fn test_test_scaffolding_can_be_stripped() {
+        let options = Options { test_scaffolding: Scaffolding::Strip, ..Options::default() };
+        let input = "    // Text\n    #[test]\n    fn test_a() {\n        assert!(true);\n    }\n    fn helper() {}\n    \
+                     // rust2md: full\n    // More\n    #[test]\n    fn test_b() {\n        assert!(true);\n    }\n";
+        let mut out = Vec::new();
+        convert(input.as_bytes(), &mut out, &options).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Text\n\n``` rust\nassert!(true);\n\nfn helper() {}\n```\n\n\
+             More\n\n``` rust\n#[test]\nfn test_b() {\n    assert!(true);\n}\n```\n\n"
+        );
+    }
+
+    #[test]
+    fn test_directives_hide_and_collapse_code() {
+        let input = "// Text\nlet a = 1;\n// rust2md: hide-start\nlet b = 2;\n// rust2md: hide-end\nlet c = 3;\n\
+                     // rust2md: hide-next\n#[allow(unused)]\nfn hidden() {\n}\n\
+                     // rust2md: collapse \"Helper\"\n\nfn helper() {\n    f();\n}\n\nlet d = 4;\n// rust2md: shout\n";
+        let mut out = Vec::new();
+        let report = convert(input.as_bytes(), &mut out, &Options::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Text\n\n``` rust\nlet a = 1;\nlet c = 3;\n```\n\n\
+             \n<details>\n<summary>Helper</summary>\n\n``` rust\nfn helper() {\n    f();\n}\n```\n\n</details>\n\n\
+             \n``` rust\nlet d = 4;\n```\n\n"
+        );
+        assert_eq!(report.diagnostics, vec![Diagnostic::new(18, String::from("unknown directive 'shout'"))]);
+    }
+
+    #[test]
+    fn test_compile_fail_examples_are_snippets() {
+        let input = "// Text\nlet a = 1;\n// rust2md: compile-fail E0382\n/*\nlet s = a;\n\n    f(s);\n*/\nlet b = 2;\n\
+                     // rust2md: compile-fail\nlet c = 3;\n";
+        let mut out = Vec::new();
+        let report = convert(input.as_bytes(), &mut out, &Options::default()).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Text\n\n``` rust\nlet a = 1;\n```\n\n\n``` {.rust .compile_fail}\nlet s = a;\n\n    f(s);\n```\n\n\
+             \n``` rust\nlet b = 2;\nlet c = 3;\n```\n\n"
+        );
+        assert_eq!(
+            report.diagnostics,
+            vec![Diagnostic::new(11, String::from("compile-fail must be followed by a /* */ comment holding the example"))]
+        );
+    }
+
+    #[test]
+    fn test_fences_can_carry_mdbook_attributes() {
+        let input = "// Text\n#[test]\n#[should_panic]\nfn test_a() {}\n// More\n#[test]\n#[ignore = \"slow\"]\nfn test_b() {}\n\
+                     // rust2md: compile-fail\n/* let a: u8 = \"a\"; */\n";
+        let mut out = Vec::new();
+        let options = Options { fences: Fences::MdBook, source_lines: true, ..Options::default() };
+        convert(input.as_bytes(), &mut out, &options).unwrap();
+        let fences: Vec<String> = String::from_utf8(out).unwrap().lines().filter(|l| l.starts_with("``` ")).map(String::from).collect();
+        assert_eq!(fences, ["``` rust,should_panic", "``` rust,ignore", "``` rust,compile_fail"]);
+    }
+
+    #[test]
+    fn test_cross_references_are_reported() {
+        let report = convert("// See [[data_types#slices]]\n// and `[[not_one]]`\nlet a = 1;\n// [[test_clone|a clone]]\n".as_bytes(), Vec::new(), &Options::default()).unwrap();
+        let references: Vec<(usize, &str)> = report.references.iter().map(|r| (r.line, r.target.as_str())).collect();
+        assert_eq!(references, [(1, "data_types#slices"), (4, "test_clone")]);
+        assert_eq!(report.references[1].label.as_deref(), Some("a clone"));
+    }
+}
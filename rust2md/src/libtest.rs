@@ -0,0 +1,161 @@
+//
+// Runs a chapter's tests with `cargo test`, and reads what libtest
+// prints for each of them:
+//
+//     test tests::test_booleans ... ok
+//     test tests::test_overflow ... FAILED
+//     test tests::test_slow ... ignored, takes too long
+//
+// Each snippet is then stamped with the outcome of the tests it
+// defines, so the book shows which of its assertions hold. Snippets
+// name their tests without the module they are in, so a name that two
+// modules use is ambiguous, and reported rather than guessed at.
+//
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::Command;
+
+use crate::diagnostics::Diagnostic;
+use crate::document::{Block, Document};
+use crate::xref;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Ignored,
+    Failed,
+}
+
+impl Outcome {
+    pub fn name(self) -> &'static str {
+        match self {
+            Outcome::Passed => "passed",
+            Outcome::Ignored => "ignored",
+            Outcome::Failed => "failed",
+        }
+    }
+}
+
+//
+// The outcome of every test of the crate in `dir`, by its path, as in
+// `tests::test_booleans`.
+// Tests that fail are an outcome; tests that do not build are an
+// error.
+//
+pub fn run(dir: &Path) -> Result<BTreeMap<String, Outcome>, String> {
+    let manifest = dir.join("Cargo.toml");
+    let output = Command::new("cargo")
+        .arg("test")
+        .arg("--manifest-path")
+        .arg(&manifest)
+        .args(["--", "--color", "never"])
+        .env("CARGO_TERM_COLOR", "never")
+        .output()
+        .map_err(|e| format!("cannot run cargo test for '{}': {}", manifest.display(), e))?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    if !stdout.lines().any(|line| line.starts_with("test result: ")) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let errors: Vec<&str> = stderr.lines().filter(|line| line.starts_with("error")).collect();
+        return Err(format!("the tests of '{}' do not build: {}", manifest.display(), errors.join("; ")));
+    }
+    Ok(parse(&stdout))
+}
+
+pub fn parse(output: &str) -> BTreeMap<String, Outcome> {
+    let mut outcomes = BTreeMap::new();
+    for line in output.lines() {
+        let (path, result) = match line.strip_prefix("test ").and_then(|rest| rest.split_once(" ... ")) {
+            Some(test) => test,
+            None => continue,
+        };
+        let outcome = match result.trim() {
+            "ok" => Outcome::Passed,
+            "FAILED" => Outcome::Failed,
+            result if result.starts_with("ignored") => Outcome::Ignored,
+            _ => continue,
+        };
+        outcomes.insert(path.to_string(), outcome);
+    }
+    outcomes
+}
+
+//
+// Gives every snippet the outcomes of the tests it defines. Snippets
+// without tests get none. A test whose name is found in more than one
+// module gets none either, and a diagnostic instead.
+//
+pub fn stamp(document: &mut Document, outcomes: &BTreeMap<String, Outcome>) {
+    let mut ambiguous = Vec::new();
+    for block in &mut document.blocks {
+        if let Block::Code(snippet) = block {
+            // The tests of an example that does not compile never ran
+            if snippet.compile_fail.is_some() {
+                continue;
+            }
+            let code = snippet.lines.join("\n");
+            snippet.tests.clear();
+            for name in xref::items(&code) {
+                let paths: Vec<(&String, &Outcome)> =
+                    outcomes.iter().filter(|(path, _)| path.rsplit("::").next() == Some(name.as_str())).collect();
+                match paths.as_slice() {
+                    [] => {}
+                    [(_, &outcome)] => snippet.tests.push((name, outcome)),
+                    _ => {
+                        let signature = format!("fn {}", name);
+                        let offset = snippet.lines.iter().position(|line| line.contains(&signature)).unwrap_or(0);
+                        let paths: Vec<&str> = paths.iter().map(|(path, _)| path.as_str()).collect();
+                        let message = format!("test name '{}' is ambiguous ({}), so its outcome is not shown", name, paths.join(", "));
+                        ambiguous.push(Diagnostic::new(snippet.first_line + offset, message));
+                    }
+                }
+            }
+        }
+    }
+    document.diagnostics.extend(ambiguous);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse as parse_rust, Options};
+
+    const OUTPUT: &str = "\nrunning 3 tests\ntest tests::test_a ... ok\ntest tests::test_b ... FAILED\n\
+                          test tests::test_c ... ignored, slow\n\nfailures:\n\n---- tests::test_b stdout ----\n\n\
+                          test result: FAILED. 1 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out\n";
+
+    #[test]
+    fn test_parse() {
+        let outcomes = parse(OUTPUT);
+        let expected = [("tests::test_a", Outcome::Passed), ("tests::test_b", Outcome::Failed), ("tests::test_c", Outcome::Ignored)];
+        assert_eq!(outcomes, expected.iter().map(|(n, o)| (n.to_string(), *o)).collect());
+    }
+
+    #[test]
+    fn test_stamp() {
+        let source = "// A\nfn test_a() {}\nfn helper() {}\n// B\nfn test_b() {}\nfn test_c() {}\n";
+        let mut document = parse_rust(source.as_bytes(), &Options::default()).unwrap();
+        stamp(&mut document, &parse(OUTPUT));
+        let snippets: Vec<_> = document.snippets().map(|s| (s.tests.clone(), s.outcome())).collect();
+        assert_eq!(
+            snippets,
+            [
+                (vec![(String::from("test_a"), Outcome::Passed)], Some(Outcome::Passed)),
+                (vec![(String::from("test_b"), Outcome::Failed), (String::from("test_c"), Outcome::Ignored)], Some(Outcome::Failed)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_names() {
+        let output = "test a::test_x ... ok\ntest b::test_x ... FAILED\ntest b::test_y ... ok\n";
+        let source = "// A\nfn test_x() {}\nfn test_y() {}\n";
+        let mut document = parse_rust(source.as_bytes(), &Options::default()).unwrap();
+        stamp(&mut document, &parse(output));
+        let snippet = document.snippets().next().unwrap();
+        assert_eq!(snippet.tests, [(String::from("test_y"), Outcome::Passed)]);
+        assert_eq!(
+            document.diagnostics,
+            [Diagnostic::new(2, "test name 'test_x' is ambiguous (a::test_x, b::test_x), so its outcome is not shown")]
+        );
+    }
+}
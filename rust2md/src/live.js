@@ -0,0 +1,24 @@
+// Added by `rust2md serve` to every page it serves: reloads the page
+// when the book is rebuilt, and shows why when a build fails
+(function () {
+  var events = new EventSource("/__rust2md/events");
+  events.addEventListener("reload", function () {
+    location.reload();
+  });
+  events.addEventListener("failed", function (event) {
+    var overlay = document.getElementById("rust2md-overlay");
+    if (!overlay) {
+      overlay = document.createElement("div");
+      overlay.id = "rust2md-overlay";
+      overlay.title = "Click to dismiss";
+      overlay.style.cssText =
+        "position: fixed; inset: 0; z-index: 1000; overflow: auto; padding: 2em; cursor: pointer;" +
+        "background: rgba(0, 0, 0, 0.85); color: #ff8182; font: 14px/1.5 monospace; white-space: pre-wrap";
+      overlay.onclick = function () {
+        overlay.remove();
+      };
+      document.body.appendChild(overlay);
+    }
+    overlay.textContent = "The book did not build:\n\n" + event.data + "\n\n(the terminal has the details)";
+  });
+})();
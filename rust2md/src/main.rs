@@ -1,89 +1,533 @@
-use std::io::{self, BufRead};
+use std::io::{self, BufReader, Write};
 use std::env;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::process;
 
-struct State {
-    started_parsing: bool,
-    start_of_comment_at: usize,
-    parsing_code : bool,
+use rust2md::book::{self, BookOptions};
+use rust2md::diagnostics;
+use rust2md::manifest::Format;
+use rust2md::output::{self, OutputFile};
+use rust2md::scaffolding::Scaffolding;
+use rust2md::{serve, watch};
+use rust2md::{convert, BlockComments, DocComments, Fences, Options};
+
+//
+// Command line arguments, as understood by rust2md. When no inputs
+// are given, stdin is read instead; when no output is given, the
+// result goes to stdout.
+//
+struct Args {
+    inputs: Vec<PathBuf>,
+    output: Option<PathBuf>,
+    options: Options,
+    strict: bool,
+    check: bool,
 }
 
-fn main() -> io::Result<()> {
-
-    let args: Vec<String> = env::args().collect();
-    if args.len() > 1 {
-        println!("rust2md is a utility that converts a Rust file to markdown following these rules:");
-        println!();
-        println!("1. Code is ignored until the first comment appears");
-        println!("2. Comments using // are interpreted as containing markdown text");
-        println!("3. Comments using /* */ are not parsed");
-        println!("4. Code following comments will be included using the markdown code fence notation");
-        println!("5. Code should start in the last column as the last comment");
-        println!("");
-        println!("Usage: rust2md < input.rs > output.md");
-        process::exit(0);
-    }
-
-    let mut state = State{
-        started_parsing : false,
-        start_of_comment_at : 0,
-        parsing_code : false,
-    };
+enum Command {
+    Convert(Args),
+    Book(BookOptions),
+    Watch(BookOptions),
+    Serve(BookOptions, u16),
+    Help,
+    Version,
+}
 
-    //let file = &args[1];
-
-    //let file = File::open(file)?; // Open the file
-    let stdin = io::stdin();
-    let handle = stdin.lock();
-    let reader = io::BufReader::new(handle); // Create a buffered reader
-    for line in reader.lines() {
-        let line = line?; // Handle potential errors
-        let line_trimmed = line.trim_start();
-        // 
-        // We found comments // which we assume to contain markdown text
-        //
-        if line_trimmed.starts_with("//") {
-            //
-            // If we were parsing code before, we need to close the
-            // code snippet
-            //
-            if state.started_parsing == true && state.parsing_code == true {
-                println!("```");
-                println!();
-            }
-            state.started_parsing = true;
-            state.parsing_code = false;
-            state.start_of_comment_at = line.find('/').unwrap();
-
-            if line_trimmed.len() < 3 {
-                /* Empty comment */
-                println!();
-            } else {
-                println!("{}", &line[state.start_of_comment_at+3..]);
+fn print_help() {
+    println!("rust2md is a utility that converts a Rust file to markdown following these rules:");
+    println!();
+    println!("1. Code is ignored until the first comment appears");
+    println!("2. Comments using // (and //!) are interpreted as containing markdown text");
+    println!("3. Comments using /* */ are kept in the code (see --block-comments)");
+    println!("4. Code following comments will be included using the markdown code fence notation");
+    println!("5. Code should start in the last column as the last comment");
+    println!();
+    println!("Comments starting with 'rust2md:' are directives about the code that follows,");
+    println!("not prose: hide-start and hide-end leave out the code between them, hide-next");
+    println!("leaves out the next item, and collapse \"Summary\" folds the next item away in");
+    println!("a <details> block. Code between // region: name and // endregion can be shown");
+    println!("again elsewhere with include name, or include chapter#name in book mode.");
+    println!("compile-fail E0382 shows the /* */ comment after it as an example that must");
+    println!("not compile; book --run-tests compiles it and shows the errors it gives.");
+    println!();
+    println!("Usage: rust2md [OPTIONS] [INPUT...]");
+    println!("       rust2md < input.rs > output.md");
+    println!("       rust2md book [--root DIR] [-o DIR] [--drafts] [--list] [--strict] [--check]");
+    println!("                    [--run-tests]");
+    println!("       rust2md epub [--root DIR] [-o DIR] [--drafts] [--strict] [--run-tests]");
+    println!("       rust2md latex [--root DIR] [-o DIR] [--drafts] [--strict] [--run-tests]");
+    println!("       rust2md watch [--root DIR] [-o DIR] [--drafts] [--strict] [--run-tests]");
+    println!("       rust2md serve [--port N] [--root DIR] [-o DIR] [--drafts] [--strict]");
+    println!("                     [--run-tests]");
+    println!("       rust2md --check");
+    println!();
+    println!("Options:");
+    println!("  -o, --output <PATH>  Write to PATH instead of stdout. If PATH is a directory");
+    println!("                       (or ends with '/'), one .md file is written per input");
+    println!("      --strict         Exit with an error if any warning was found");
+    println!("      --check          Write nothing; compare the output with the files on disk,");
+    println!("                       print a diff for each one that is out of date, and exit");
+    println!("                       with an error if any is. Without inputs, checks the book");
+    println!("  -h, --help           Print this help text");
+    println!("  -V, --version        Print the version");
+    println!();
+    println!("Conversion options, also accepted by book mode:");
+    println!("      --block-comments <keep|strip|prose>");
+    println!("                       What to do with /* */ comments: keep them in the code");
+    println!("                       (the default), strip them from the snippets, or render");
+    println!("                       /*! */ comments that stand on their own lines as prose");
+    println!("      --doc-comments <attach|lift>");
+    println!("                       Keep /// comments in the snippet with the item they");
+    println!("                       document (the default), or lift them out as prose");
+    println!("      --tab-width <N>  Columns a tab advances to when measuring indentation");
+    println!("                       (default: 4)");
+    println!("      --test-scaffolding <keep|strip>");
+    println!("                       Show tests whole (the default), or only the bodies of");
+    println!("                       #[test] functions, dedented. A // rust2md: full comment");
+    println!("                       in or before a snippet shows that snippet whole");
+    println!("      --source-lines   Say where each snippet came from in its fence, as in");
+    println!("                       ``` {{.rust startFrom=17 file=src/control_flow/src/main.rs}}");
+    println!("      --fences <pandoc|mdbook>");
+    println!("                       Write fence attributes the pandoc way (the default), or");
+    println!("                       the mdBook way, as in ``` rust,should_panic, from the");
+    println!("                       attributes of each snippet's tests");
+    println!();
+    println!("Book mode converts the chapters listed in <root>/rust2md.toml (default root: .)");
+    println!("into <output>/<chapter>.md, and concatenates them, after the book's front matter,");
+    println!("into <output>/index.md. Without a manifest, every src/*/src/main.rs is a chapter,");
+    println!("in alphabetical order, and the front matter is read from md/header.md.");
+    println!("With \"html\" among the manifest's output formats, each chapter also gets a");
+    println!("standalone <chapter>.html page, and the whole book goes into index.html.");
+    println!("With \"mdbook\", <output>/mdbook gets a book.toml and a src/SUMMARY.md, with one");
+    println!("file per chapter, ready for mdbook build. With \"epub\", or with rust2md epub,");
+    println!("the book goes into an EPUB 3 file named after its title, such as");
+    println!("<output>/rust-by-assertion.epub, to be read offline. With \"latex\", or with");
+    println!("rust2md latex, it goes into a LaTeX document such as <output>/rust-by-assertion.tex;");
+    println!("xelatex or lualatex, run twice, turn it into a PDF for print.");
+    println!("The table of contents is written to toc.md and toc.json. Heading anchors are");
+    println!("unique across the book, so they are the same in every format.");
+    println!("Prose may link to a chapter, a heading or an item with [[data_types#slices]],");
+    println!("[[slices]] or [[test_clone]]; references that lead nowhere are warnings.");
+    println!("Every snippet in the HTML links to the source lines it shows, at the manifest's");
+    println!("source_url if set. With source_lines = true, the markdown fences say where they");
+    println!("came from too, and source_map = true writes them all to sourcemap.json.");
+    println!("Watch mode builds the book, then builds it again whenever a file below the root");
+    println!("changes. Only the chapters whose source changed are converted (and, with");
+    println!("--run-tests, tested) again; the rest of the book is put back together from them.");
+    println!("Serve mode does the same, and serves the book at http://localhost:3000/ (or the");
+    println!("port given with --port). Pages reload after each build, and show an error over");
+    println!("themselves when one fails.");
+    println!();
+    println!("  -o, --output <DIR>   Write to DIR instead of the manifest's output directory");
+    println!("      --drafts         Include chapters marked as drafts in the index");
+    println!("      --list           Print the chapters in reading order and exit");
+    println!("      --run-tests      Run each chapter's tests and mark every snippet as passed,");
+    println!("                       failed or ignored; any failing test fails the build,");
+    println!("                       as does a compile-fail example that compiles or fails");
+    println!("                       with another error code");
+}
+
+//
+// Options that change how a file is converted, shared by the plain
+// and the book command lines. Returns whether `arg` was one of them.
+//
+fn parse_convert_option<'a>(arg: &str, iter: &mut impl Iterator<Item = &'a String>, options: &mut Options) -> Result<bool, String> {
+    match arg {
+        "--block-comments" => {
+            let value = iter.next().ok_or_else(|| format!("'{}' requires one of keep, strip or prose", arg))?;
+            options.block_comments = BlockComments::parse(value)
+                .ok_or_else(|| format!("invalid value '{}' for '{}': expected keep, strip or prose", value, arg))?;
+        }
+        "--doc-comments" => {
+            let value = iter.next().ok_or_else(|| format!("'{}' requires one of attach or lift", arg))?;
+            options.doc_comments = DocComments::parse(value)
+                .ok_or_else(|| format!("invalid value '{}' for '{}': expected attach or lift", value, arg))?;
+        }
+        "--tab-width" => {
+            let value = iter.next().ok_or_else(|| format!("'{}' requires a number", arg))?;
+            options.tab_width = value.parse()
+                .map_err(|_| format!("invalid value '{}' for '{}': expected a number", value, arg))?;
+        }
+        "--test-scaffolding" => {
+            let value = iter.next().ok_or_else(|| format!("'{}' requires one of keep or strip", arg))?;
+            options.test_scaffolding = Scaffolding::parse(value)
+                .ok_or_else(|| format!("invalid value '{}' for '{}': expected keep or strip", value, arg))?;
+        }
+        "--source-lines" => options.source_lines = true,
+        "--fences" => {
+            let value = iter.next().ok_or_else(|| format!("'{}' requires one of pandoc or mdbook", arg))?;
+            options.fences = Fences::parse(value)
+                .ok_or_else(|| format!("invalid value '{}' for '{}': expected pandoc or mdbook", value, arg))?;
+        }
+        _ => return Ok(false),
+    }
+    Ok(true)
+}
+
+// The options that take a value, which may also be given as `--name=value`
+const VALUE_OPTIONS: &[&str] =
+    &["--output", "--root", "--port", "--block-comments", "--doc-comments", "--tab-width", "--test-scaffolding", "--fences"];
+
+//
+// The arguments with every `--name=value` split in two, as if given as
+// `--name value`. Nothing after `--` is an option, so nothing there is
+// split.
+//
+fn split_values(args: &[String]) -> Vec<String> {
+    let mut split = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--" {
+            split.push(arg.clone());
+            split.extend(iter.by_ref().cloned());
+            break;
+        }
+        match arg.split_once('=') {
+            Some((name, value)) if VALUE_OPTIONS.contains(&name) => split.extend([name.to_string(), value.to_string()]),
+            _ => split.push(arg.clone()),
+        }
+    }
+    split
+}
+
+fn parse_book_args(args: &[String], command: &str) -> Result<Command, String> {
+    let mut options = BookOptions {
+        root: PathBuf::from("."),
+        convert: Options::default(),
+        output: None,
+        drafts: false,
+        list: false,
+        strict: false,
+        check: false,
+        run_tests: false,
+        formats: Vec::new(),
+    };
+    let mut port = serve::DEFAULT_PORT;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "--port" if command == "serve" => {
+                let value = iter.next().ok_or_else(|| format!("'{}' requires a number", arg))?;
+                port = value.parse().map_err(|_| format!("invalid value '{}' for '{}': expected a port number", value, arg))?;
             }
-        } else if state.started_parsing == true {
-            //
-            // If we haven't found a comment, then we assume we
-            // are embedding a code snippet
-            //
-            if state.parsing_code == false {
-                    println!();
-                    println!("``` rust");
-                    state.parsing_code = true;
+            "--drafts" => options.drafts = true,
+            "--list" => options.list = true,
+            "--strict" => options.strict = true,
+            "--check" => options.check = true,
+            "--run-tests" => options.run_tests = true,
+            "--root" => match iter.next() {
+                Some(path) => options.root = PathBuf::from(path),
+                None => return Err(format!("'{}' requires a path", arg)),
+            },
+            "-o" | "--output" => match iter.next() {
+                Some(path) => options.output = Some(PathBuf::from(path)),
+                None => return Err(format!("'{}' requires a path", arg)),
+            },
+            // Book mode takes no inputs, so nothing may follow `--`
+            "--" => {
+                if let Some(arg) = iter.next() {
+                    return Err(format!("unexpected argument '{}' for '{}'", arg, command));
+                }
             }
-            if line.len() >= state.start_of_comment_at {
-                println!("{}", &line[state.start_of_comment_at..]);
-            } else if line.len() == 0 { /* Empty line */
-                println!();
+            _ if parse_convert_option(arg, &mut iter, &mut options.convert)? => {}
+            _ => return Err(format!("unexpected argument '{}' for '{}'", arg, command)),
+        }
+    }
+    if command != "book" && (options.check || options.list) {
+        let arg = if options.check { "--check" } else { "--list" };
+        return Err(format!("unexpected argument '{}' for '{}'", arg, command));
+    }
+    match command {
+        "epub" => Ok(Command::Book(BookOptions { formats: vec![Format::Epub], ..options })),
+        "latex" => Ok(Command::Book(BookOptions { formats: vec![Format::Latex], ..options })),
+        "watch" => Ok(Command::Watch(options)),
+        "serve" => Ok(Command::Serve(options, port)),
+        _ => Ok(Command::Book(options)),
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Command, String> {
+    let args = &split_values(args);
+    if let Some(command) = args.first().filter(|arg| ["book", "epub", "latex", "watch", "serve"].contains(&arg.as_str())) {
+        return parse_book_args(&args[1..], command);
+    }
+    let mut inputs = Vec::new();
+    let mut output = None;
+    let mut options = Options::default();
+    let mut strict = false;
+    let mut check = false;
+    let mut only_inputs = false;
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if only_inputs {
+            inputs.push(PathBuf::from(arg));
+            continue;
+        }
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-o" | "--output" => match iter.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return Err(format!("'{}' requires a path", arg)),
+            },
+            "--strict" => strict = true,
+            "--check" => check = true,
+            "--" => only_inputs = true,
+            _ if parse_convert_option(arg, &mut iter, &mut options)? => {}
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return Err(format!("unknown option '{}'", arg));
             }
+            _ => inputs.push(PathBuf::from(arg)),
         }
     }
-    // If the last thing we did was parse a code snippet,
-    // we need to close it
-    if state.started_parsing == true && state.parsing_code == true {
-        println!("```");
-        println!();
+    //
+    // With nothing to convert, `--check` checks the whole book
+    //
+    if check && inputs.is_empty() && output.is_none() {
+        let mut book_args = vec![String::from("--check")];
+        book_args.extend(args.iter().filter(|arg| *arg != "--check" && *arg != "--").cloned());
+        return parse_book_args(&book_args, "book");
+    }
+    if check && output.is_none() {
+        return Err(String::from("'--check' needs an output (-o) to compare with"));
     }
+    Ok(Command::Convert(Args { inputs, output, options, strict, check }))
+}
 
+//
+// The markdown file name for an input. Chapters live in
+// `src/<chapter>/src/main.rs`, so `main.rs` and `lib.rs` are named
+// after their crate directory rather than after themselves.
+//
+fn output_name(input: &Path) -> PathBuf {
+    let stem = input.file_stem().and_then(|s| s.to_str()).unwrap_or("output");
+    let name = if stem == "main" || stem == "lib" {
+        input
+            .parent()
+            .filter(|p| p.file_name().is_some_and(|n| n == "src"))
+            .and_then(|p| p.parent())
+            .and_then(|p| p.file_name())
+            .and_then(|n| n.to_str())
+            .unwrap_or(stem)
+    } else {
+        stem
+    };
+    PathBuf::from(format!("{}.md", name))
+}
+
+// The options for converting `input`, whose fences name it
+fn input_options(input: &Path, options: &Options) -> Options {
+    Options { source: Some(input.display().to_string()), ..options.clone() }
+}
+
+fn open_input(path: &Path) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("cannot read '{}': {}", path.display(), e))
+}
+
+fn run(args: Args) -> Result<(), String> {
+    //
+    // Every input is opened before anything is written, so that a
+    // missing input does not leave half-written output behind
+    //
+    let mut readers = Vec::new();
+    for input in &args.inputs {
+        readers.push((input, open_input(input)?));
+    }
+
+    let output_dir = args.output.as_ref().filter(|path| {
+        path.is_dir() || path.to_string_lossy().ends_with('/')
+    });
+
+    let mut problems = 0;
+    let mut files = Vec::new();
+    match (&args.output, output_dir) {
+        (Some(dir), Some(_)) => {
+            if readers.is_empty() {
+                return Err(format!("an input file is required when writing to the directory '{}'", dir.display()));
+            }
+            for (input, reader) in readers {
+                let mut markdown = Vec::new();
+                let report = convert(reader, &mut markdown, &input_options(input, &args.options))
+                    .map_err(|e| format!("cannot convert '{}': {}", input.display(), e))?;
+                problems += diagnostics::print(&input.display().to_string(), &report.diagnostics, args.strict);
+                files.push(OutputFile::new(dir.join(output_name(input)), markdown));
+            }
+        }
+        (Some(path), None) => {
+            let mut markdown = Vec::new();
+            problems = convert_all(readers, &mut markdown, &args)?;
+            files.push(OutputFile::new(path.clone(), markdown));
+        }
+        (None, _) => {
+            let stdout = io::stdout();
+            let mut out = stdout.lock();
+            problems = convert_all(readers, &mut out, &args)?;
+        }
+    }
+    if args.check {
+        let stale = output::check_all(&files);
+        if !stale.is_empty() {
+            return Err(output::stale_failure(&stale, "rust2md"));
+        }
+    } else {
+        output::write_all(&files)?;
+    }
+    if args.strict && problems > 0 {
+        return Err(diagnostics::strict_failure(problems));
+    }
     Ok(())
 }
+
+//
+// Several inputs going to the same place are concatenated, the same
+// way the chapters are concatenated into `docs/index.md`
+//
+fn convert_all<W: Write>(readers: Vec<(&PathBuf, BufReader<File>)>, out: &mut W, args: &Args) -> Result<usize, String> {
+    if readers.is_empty() {
+        let stdin = io::stdin();
+        let report = convert(stdin.lock(), out, &args.options).map_err(|e| format!("cannot convert stdin: {}", e))?;
+        return Ok(diagnostics::print("<stdin>", &report.diagnostics, args.strict));
+    }
+    let mut problems = 0;
+    for (input, reader) in readers {
+        let report = convert(reader, &mut *out, &input_options(input, &args.options))
+            .map_err(|e| format!("cannot convert '{}': {}", input.display(), e))?;
+        problems += diagnostics::print(&input.display().to_string(), &report.diagnostics, args.strict);
+    }
+    Ok(problems)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let status = execute(&args);
+    if status != 0 {
+        process::exit(status);
+    }
+}
+
+//
+// Runs the command line `args`, and returns the exit status: 1 if the
+// command failed, and 2 if the command line itself is wrong
+//
+fn execute(args: &[String]) -> i32 {
+    let command = match parse_args(args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("rust2md: {}", message);
+            eprintln!("Try 'rust2md --help' for more information.");
+            return 2;
+        }
+    };
+    let result = match command {
+        Command::Help => {
+            print_help();
+            Ok(())
+        }
+        Command::Version => {
+            println!("rust2md {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+        Command::Convert(args) => run(args),
+        Command::Book(options) => book::build(&options),
+        Command::Watch(options) => watch::run(&options),
+        Command::Serve(options, port) => serve::run(&options, port),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(message) => {
+            eprintln!("rust2md: {}", message);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn convert_args(line: &[&str]) -> Args {
+        match parse_args(&args(line)) {
+            Ok(Command::Convert(args)) => args,
+            Ok(_) => panic!("{:?} is not a conversion", line),
+            Err(message) => panic!("{:?}: {}", line, message),
+        }
+    }
+
+    fn book_options(line: &[&str]) -> BookOptions {
+        match parse_args(&args(line)) {
+            Ok(Command::Book(options)) => options,
+            Ok(_) => panic!("{:?} does not build the book", line),
+            Err(message) => panic!("{:?}: {}", line, message),
+        }
+    }
+
+    #[test]
+    fn test_inputs_and_output() {
+        let args = convert_args(&["a.rs", "--output=out.md", "--tab-width=8", "b.rs", "--", "-c.rs"]);
+        assert_eq!(args.inputs, ["a.rs", "b.rs", "-c.rs"].map(PathBuf::from));
+        assert_eq!(args.output, Some(PathBuf::from("out.md")));
+        assert_eq!(args.options.tab_width, 8);
+        assert_eq!(convert_args(&["-o", "docs/", "a.rs"]).output, Some(PathBuf::from("docs/")));
+        assert!(convert_args(&[]).inputs.is_empty());
+    }
+
+    #[test]
+    fn test_book_args() {
+        let options = book_options(&["book", "--output=/tmp/o", "--root", "book", "--fences=mdbook", "--"]);
+        assert_eq!(options.output, Some(PathBuf::from("/tmp/o")));
+        assert_eq!(options.root, PathBuf::from("book"));
+        assert_eq!(options.convert.fences, Fences::MdBook);
+        // With nothing to convert, --check checks the book, `--` or not
+        assert!(book_options(&["--check", "--"]).check);
+        assert_eq!(book_options(&["epub"]).formats, [Format::Epub]);
+    }
+
+    #[test]
+    fn test_bad_args() {
+        let error = |line: &[&str]| parse_args(&args(line)).err().unwrap_or_default();
+        assert_eq!(error(&["--strict=yes"]), "unknown option '--strict=yes'");
+        assert_eq!(error(&["book", "--", "a.rs"]), "unexpected argument 'a.rs' for 'book'");
+        assert_eq!(error(&["watch", "--check"]), "unexpected argument '--check' for 'watch'");
+        assert_eq!(error(&["-o"]), "'-o' requires a path");
+        assert_eq!(error(&["--check", "a.rs"]), "'--check' needs an output (-o) to compare with");
+        assert_eq!(execute(&args(&["--bogus"])), 2);
+    }
+
+    #[test]
+    fn test_output_file_or_directory() {
+        let dir = env::temp_dir().join(format!("rust2md-main-{}", process::id()));
+        fs::create_dir_all(dir.join("src/a/src")).unwrap();
+        let (a, b) = (dir.join("src/a/src/main.rs"), dir.join("b.rs"));
+        fs::write(&a, "// A\nlet a = 1;\n").unwrap();
+        fs::write(&b, "// B\n").unwrap();
+        let path = |path: &Path| path.display().to_string();
+
+        // A file gets every input, one after the other
+        let status = execute(&args(&[&path(&a), &path(&b), "-o", &path(&dir.join("book.md"))]));
+        let book = fs::read_to_string(dir.join("book.md"));
+        // A directory gets a file for each, named after its crate or itself
+        let out = format!("{}/", path(&dir.join("out")));
+        let statuses = [status, execute(&args(&[&path(&a), &path(&b), "-o", &out]))];
+        let (a_md, b_md) = (fs::read_to_string(dir.join("out/a.md")), fs::read_to_string(dir.join("out/b.md")));
+        // A missing input fails, and writes nothing
+        let missing = execute(&args(&[&path(&a), &path(&dir.join("missing.rs")), "-o", &path(&dir.join("none.md"))]));
+        let none = dir.join("none.md").exists();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(statuses, [0, 0]);
+        assert_eq!(book.unwrap(), "A\n\n``` rust\nlet a = 1;\n```\n\nB\n");
+        assert_eq!(a_md.unwrap(), "A\n\n``` rust\nlet a = 1;\n```\n\n");
+        assert_eq!(b_md.unwrap(), "B\n");
+        assert_eq!((missing, none), (1, false));
+    }
+}
@@ -0,0 +1,360 @@
+//
+// The book manifest, `rust2md.toml`, which sits at the root of the
+// book. It holds the book's metadata, where and what to write, and
+// the list of chapters in reading order:
+//
+//     [book]
+//     title = "Rust by Assertion"
+//     author = "Ernesto Garbarino"
+//     date = "2025-02-18"
+//
+//     [output]
+//     dir = "docs"
+//     formats = ["markdown", "html", "mdbook"]
+//     theme = "auto"
+//     source_lines = true
+//     source_url = "https://example.com/book/blob/main/"
+//     source_map = true
+//
+//     [[chapter]]
+//     path = "src/data_types"
+//     title = "Simple Data Types"
+//     part = "Basics"
+//
+//     [[chapter]]
+//     path = "src/functions"
+//     draft = true
+//
+// Crates under `src/` that are neither listed nor in `exclude` are
+// reported and skipped. Keys the manifest does not know, which are
+// most likely typos, are errors.
+//
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::toml::{self, Table, Value};
+
+pub const FILE_NAME: &str = "rust2md.toml";
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    pub date: Option<String>,
+}
+
+impl Metadata {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.author.is_none() && self.date.is_none()
+    }
+
+    //
+    // The metadata as the front matter block pandoc expects at the top
+    // of `index.md`
+    //
+    pub fn front_matter(&self) -> String {
+        let mut s = String::from("---\n");
+        for (key, value) in [("title", &self.title), ("author", &self.author), ("date", &self.date)] {
+            if let Some(value) = value {
+                s.push_str(&format!("{}: {}\n", key, value));
+            }
+        }
+        s.push_str("---\n\n\n");
+        s
+    }
+
+    //
+    // The metadata in a front matter block like the one above, for
+    // books that still keep it in `md/header.md`
+    //
+    pub fn from_front_matter(text: &str) -> Metadata {
+        let mut metadata = Metadata::default();
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some("---") {
+            return metadata;
+        }
+        for line in lines.take_while(|line| line.trim() != "---") {
+            if let Some((key, value)) = line.split_once(':') {
+                let value = Some(value.trim().trim_matches('"').to_string());
+                match key.trim() {
+                    "title" => metadata.title = value,
+                    "author" => metadata.author = value,
+                    "date" => metadata.date = value,
+                    _ => {}
+                }
+            }
+        }
+        metadata
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Markdown,
+    Html,
+    // An mdBook source tree
+    MdBook,
+    // An EPUB 3 file, named after the book
+    Epub,
+    // A LaTeX document, named after the book, for a printed copy
+    Latex,
+}
+
+impl Format {
+    pub fn parse(name: &str) -> Option<Format> {
+        match name {
+            "markdown" => Some(Format::Markdown),
+            "html" => Some(Format::Html),
+            "mdbook" => Some(Format::MdBook),
+            "epub" => Some(Format::Epub),
+            "latex" => Some(Format::Latex),
+            _ => None,
+        }
+    }
+}
+
+//
+// The colours of the HTML output. `Auto` follows the reader's system
+// preference.
+//
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Theme {
+    #[default]
+    Auto,
+    Light,
+    Dark,
+}
+
+impl Theme {
+    pub fn parse(name: &str) -> Option<Theme> {
+        match name {
+            "auto" => Some(Theme::Auto),
+            "light" => Some(Theme::Light),
+            "dark" => Some(Theme::Dark),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub dir: PathBuf,
+    pub index: String,
+    pub formats: Vec<Format>,
+    pub theme: Theme,
+    // Whether the markdown fences say which source lines they show
+    pub source_lines: bool,
+    // What "edit source" links in the HTML start with, before the
+    // file's path from the root of the book
+    pub source_url: Option<String>,
+    // Whether to write `sourcemap.json`
+    pub source_map: bool,
+}
+
+impl Default for Output {
+    fn default() -> Output {
+        Output {
+            dir: PathBuf::from("docs"),
+            index: String::from("index.md"),
+            formats: vec![Format::Markdown],
+            theme: Theme::Auto,
+            source_lines: false,
+            source_url: None,
+            source_map: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChapterEntry {
+    pub path: PathBuf,
+    pub title: Option<String>,
+    pub part: Option<String>,
+    pub draft: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Manifest {
+    pub book: Metadata,
+    pub output: Output,
+    pub chapters: Vec<ChapterEntry>,
+    pub exclude: Vec<PathBuf>,
+}
+
+impl Manifest {
+    //
+    // Reads the manifest in `root`. A book without a manifest is
+    // fine, so a missing file gives `None` rather than an error.
+    //
+    pub fn load(root: &Path) -> Result<Option<Manifest>, String> {
+        let path = root.join(FILE_NAME);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("cannot read '{}': {}", path.display(), e)),
+        };
+        Manifest::parse(&text)
+            .map(Some)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Manifest, String> {
+        let table = toml::parse(text)?;
+        let mut manifest = Manifest::default();
+        known_keys(&table, None, &["book", "output", "chapter", "exclude"])?;
+
+        if let Some(book) = table.get("book") {
+            let book = book.as_table().ok_or("'book' must be a table")?;
+            known_keys(book, Some("book"), &["title", "author", "date"])?;
+            manifest.book = Metadata {
+                title: string(book, "book", "title")?,
+                author: string(book, "book", "author")?,
+                date: string(book, "book", "date")?,
+            };
+        }
+
+        if let Some(output) = table.get("output") {
+            let output = output.as_table().ok_or("'output' must be a table")?;
+            known_keys(output, Some("output"), &["dir", "index", "formats", "theme", "source_lines", "source_url", "source_map"])?;
+            if let Some(dir) = string(output, "output", "dir")? {
+                manifest.output.dir = PathBuf::from(dir);
+            }
+            if let Some(index) = string(output, "output", "index")? {
+                manifest.output.index = index;
+            }
+            if output.contains_key("formats") {
+                manifest.output.formats.clear();
+                for name in string_array(output, "output.formats", "formats")? {
+                    let format = Format::parse(&name)
+                        .ok_or_else(|| format!("unknown output format '{}'", name))?;
+                    manifest.output.formats.push(format);
+                }
+            }
+            if let Some(theme) = string(output, "output", "theme")? {
+                manifest.output.theme = Theme::parse(&theme)
+                    .ok_or_else(|| format!("unknown theme '{}', expected auto, light or dark", theme))?;
+            }
+            manifest.output.source_lines = boolean(output, "output", "source_lines")?.unwrap_or(false);
+            manifest.output.source_url = string(output, "output", "source_url")?;
+            manifest.output.source_map = boolean(output, "output", "source_map")?.unwrap_or(false);
+        }
+
+        if let Some(chapters) = table.get("chapter") {
+            let chapters = chapters.as_array().ok_or("'chapter' must be an array of tables, as in [[chapter]]")?;
+            for chapter in chapters {
+                let chapter = chapter.as_table().ok_or("'chapter' must be an array of tables, as in [[chapter]]")?;
+                known_keys(chapter, Some("chapter"), &["path", "title", "part", "draft"])?;
+                let path = string(chapter, "chapter", "path")?.ok_or("every [[chapter]] needs a 'path'")?;
+                manifest.chapters.push(ChapterEntry {
+                    path: PathBuf::from(path),
+                    title: string(chapter, "chapter", "title")?,
+                    part: string(chapter, "chapter", "part")?,
+                    draft: boolean(chapter, "chapter", "draft")?.unwrap_or(false),
+                });
+            }
+        }
+
+        manifest.exclude = string_array(&table, "exclude", "exclude")?.into_iter().map(PathBuf::from).collect();
+        Ok(manifest)
+    }
+}
+
+fn known_keys(table: &Table, section: Option<&str>, keys: &[&str]) -> Result<(), String> {
+    match table.keys().find(|key| !keys.contains(&key.as_str())) {
+        None => Ok(()),
+        Some(key) => match section {
+            Some(section) => Err(format!("unknown key '{}.{}'", section, key)),
+            None => Err(format!("unknown key '{}'", key)),
+        },
+    }
+}
+
+fn string(table: &Table, section: &str, key: &str) -> Result<Option<String>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(Value::String(s)) => Ok(Some(s.clone())),
+        Some(_) => Err(format!("'{}.{}' must be a string", section, key)),
+    }
+}
+
+fn boolean(table: &Table, section: &str, key: &str) -> Result<Option<bool>, String> {
+    match table.get(key) {
+        None => Ok(None),
+        Some(value) => value.as_bool().map(Some).ok_or_else(|| format!("'{}.{}' must be true or false", section, key)),
+    }
+}
+
+fn string_array(table: &Table, name: &str, key: &str) -> Result<Vec<String>, String> {
+    let mut strings = Vec::new();
+    if let Some(value) = table.get(key) {
+        let items = value.as_array().ok_or_else(|| format!("'{}' must be an array", name))?;
+        for item in items {
+            let s = item.as_str().ok_or_else(|| format!("'{}' must only contain strings", name))?;
+            strings.push(s.to_string());
+        }
+    }
+    Ok(strings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let manifest = Manifest::parse(
+            "exclude = [\"src/game\"]\n\
+             [book]\ntitle = \"Rust by Assertion\"\nauthor = \"Ernesto Garbarino\"\n\
+             [output]\ndir = \"site\"\nformats = [\"html\", \"epub\"]\ntheme = \"dark\"\nsource_lines = true\n\
+             [[chapter]]\npath = \"src/data_types\"\ntitle = \"Simple Data Types\"\npart = \"Basics\"\n\
+             [[chapter]]\npath = \"src/functions\"\ndraft = true\n",
+        )
+        .unwrap();
+        assert_eq!(manifest.book.title.as_deref(), Some("Rust by Assertion"));
+        assert_eq!(manifest.book.date, None);
+        assert_eq!(manifest.output.dir, PathBuf::from("site"));
+        assert_eq!(manifest.output.index, "index.md");
+        assert_eq!(manifest.output.formats, [Format::Html, Format::Epub]);
+        assert_eq!(manifest.output.theme, Theme::Dark);
+        assert!(manifest.output.source_lines && !manifest.output.source_map);
+        assert_eq!(manifest.exclude, [PathBuf::from("src/game")]);
+        assert_eq!(
+            manifest.chapters,
+            [
+                ChapterEntry {
+                    path: PathBuf::from("src/data_types"),
+                    title: Some(String::from("Simple Data Types")),
+                    part: Some(String::from("Basics")),
+                    draft: false,
+                },
+                ChapterEntry { path: PathBuf::from("src/functions"), title: None, part: None, draft: true },
+            ]
+        );
+        assert_eq!(Manifest::parse("").unwrap().output, Output::default());
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| Manifest::parse(text).err().unwrap_or_default();
+        assert_eq!(error("[output]\ntheme = 1\n"), "'output.theme' must be a string");
+        assert_eq!(error("[output]\ntheme = \"sepia\"\n"), "unknown theme 'sepia', expected auto, light or dark");
+        assert_eq!(error("[output]\nformats = [\"pdf\"]\n"), "unknown output format 'pdf'");
+        assert_eq!(error("[output]\nformats = \"html\"\n"), "'output.formats' must be an array");
+        assert_eq!(error("[output]\nsource_map = \"yes\"\n"), "'output.source_map' must be true or false");
+        assert_eq!(error("[output]\nsourcelines = true\n"), "unknown key 'output.sourcelines'");
+        assert_eq!(error("[[chapter]]\ntitle = \"Untitled\"\n"), "every [[chapter]] needs a 'path'");
+        assert_eq!(error("[[chapter]]\npath = \"src/a\"\ndraf = true\n"), "unknown key 'chapter.draf'");
+        assert_eq!(error("chapter = \"src/a\"\n"), "'chapter' must be an array of tables, as in [[chapter]]");
+        assert_eq!(error("title = \"Rust\"\n"), "unknown key 'title'");
+        assert_eq!(error("exclude = [1]\n"), "'exclude' must only contain strings");
+    }
+
+    #[test]
+    fn test_front_matter() {
+        let metadata = Metadata { title: Some(String::from("Rust")), author: None, date: Some(String::from("2025-02-18")) };
+        assert_eq!(metadata.front_matter(), "---\ntitle: Rust\ndate: 2025-02-18\n---\n\n\n");
+        assert_eq!(Metadata::from_front_matter(&metadata.front_matter()), metadata);
+        assert!(Metadata::from_front_matter("title: Rust\n").is_empty());
+    }
+}
@@ -0,0 +1,850 @@
+//
+// A CommonMark parser covering what rust2md writes and what people
+// write in comments: ATX and setext headings, paragraphs, fenced and
+// indented code, block quotes, lists, thematic breaks and HTML blocks;
+// and, inline, code spans, emphasis, links, images, autolinks, raw
+// HTML, entities, escapes and line breaks. Link reference definitions
+// and tables are not supported, and come out as plain text.
+//
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    Heading { level: usize, text: Vec<Inline> },
+    Paragraph(Vec<Inline>),
+    Code { info: String, text: String },
+    Quote(Vec<Block>),
+    // `start` is the number of the first item of an ordered list
+    List { start: Option<u64>, tight: bool, items: Vec<Vec<Block>> },
+    Rule,
+    Html(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inline {
+    Text(String),
+    Code(String),
+    Emphasis(Vec<Inline>),
+    Strong(Vec<Inline>),
+    Link { url: String, title: Option<String>, text: Vec<Inline> },
+    Image { url: String, title: Option<String>, alt: Vec<Inline> },
+    Html(String),
+    SoftBreak,
+    HardBreak,
+}
+
+pub fn parse(text: &str) -> Vec<Block> {
+    let lines: Vec<String> = text.lines().map(expand_tabs).collect();
+    blocks(&lines)
+}
+
+// Tabs in the indentation count as four columns
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::new();
+    let mut rest = line;
+    while let Some(c) = rest.chars().next().filter(|&c| c == ' ' || c == '\t') {
+        if c == '\t' {
+            expanded.push_str(&" ".repeat(4 - expanded.len() % 4));
+        } else {
+            expanded.push(' ');
+        }
+        rest = &rest[1..];
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+fn indent(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_blank(line: &str) -> bool {
+    line.trim().is_empty()
+}
+
+//
+// Block structure
+//
+
+fn blocks(lines: &[String]) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let line = &lines[i];
+        let (block, next) = if is_blank(line) {
+            i += 1;
+            continue;
+        } else if let Some(fence) = Fence::open(line) {
+            fenced_code(lines, i, fence)
+        } else if indent(line) >= 4 {
+            indented_code(lines, i)
+        } else if let Some((level, text)) = atx_heading(line) {
+            (Block::Heading { level, text: inlines(text) }, i + 1)
+        } else if is_rule(line) {
+            (Block::Rule, i + 1)
+        } else if quote_line(line).is_some() {
+            quote(lines, i)
+        } else if Marker::parse(line).is_some() {
+            list(lines, i)
+        } else if html_start(line) {
+            html_block(lines, i)
+        } else {
+            paragraph(lines, i)
+        };
+        blocks.push(block);
+        i = next;
+    }
+    blocks
+}
+
+struct Fence {
+    indent: usize,
+    marker: char,
+    width: usize,
+    info: String,
+}
+
+impl Fence {
+    fn open(line: &str) -> Option<Fence> {
+        let indent = indent(line);
+        let rest = &line[indent..];
+        let marker = rest.chars().next().filter(|&c| c == '`' || c == '~')?;
+        let width = rest.chars().take_while(|&c| c == marker).count();
+        let info = rest[width..].trim();
+        if indent > 3 || width < 3 || (marker == '`' && info.contains('`')) {
+            return None;
+        }
+        Some(Fence { indent, marker, width, info: info.to_string() })
+    }
+
+    fn is_closed_by(&self, line: &str) -> bool {
+        let rest = line.trim();
+        indent(line) <= 3 && rest.len() >= self.width && rest.chars().all(|c| c == self.marker)
+    }
+}
+
+// A fence that is never closed runs to the end of the document
+fn fenced_code(lines: &[String], start: usize, fence: Fence) -> (Block, usize) {
+    let mut text = String::new();
+    let mut i = start + 1;
+    while i < lines.len() && !fence.is_closed_by(&lines[i]) {
+        let line = &lines[i];
+        text.push_str(&line[indent(line).min(fence.indent)..]);
+        text.push('\n');
+        i += 1;
+    }
+    (Block::Code { info: fence.info, text }, (i + 1).min(lines.len()))
+}
+
+fn indented_code(lines: &[String], start: usize) -> (Block, usize) {
+    let mut code: Vec<&str> = Vec::new();
+    let mut i = start;
+    while i < lines.len() && (is_blank(&lines[i]) || indent(&lines[i]) >= 4) {
+        let line = &lines[i];
+        code.push(&line[indent(line).min(4)..]);
+        i += 1;
+    }
+    while code.last().is_some_and(|line| is_blank(line)) {
+        code.pop();
+    }
+    let text = code.iter().map(|line| format!("{}\n", line)).collect();
+    (Block::Code { info: String::new(), text }, i)
+}
+
+fn atx_heading(line: &str) -> Option<(usize, &str)> {
+    let indent = indent(line);
+    let rest = &line[indent..];
+    let level = rest.chars().take_while(|&c| c == '#').count();
+    let after = &rest[level..];
+    if indent > 3 || level == 0 || level > 6 || !(after.is_empty() || after.starts_with(' ')) {
+        return None;
+    }
+    let text = after.trim();
+    // An optional closing run of `#`, after a space
+    let unclosed = text.trim_end_matches('#');
+    let text = if unclosed.is_empty() {
+        ""
+    } else if unclosed.ends_with(' ') {
+        unclosed.trim_end()
+    } else {
+        text
+    };
+    Some((level, text))
+}
+
+// The `===` or `---` under a paragraph that makes it a heading
+fn setext_level(line: &str) -> Option<usize> {
+    let rest = line.trim();
+    if indent(line) > 3 || rest.is_empty() {
+        return None;
+    }
+    if rest.chars().all(|c| c == '=') {
+        Some(1)
+    } else if rest.chars().all(|c| c == '-') {
+        Some(2)
+    } else {
+        None
+    }
+}
+
+fn is_rule(line: &str) -> bool {
+    let mut marks = line.chars().filter(|c| !c.is_whitespace());
+    let first = match marks.clone().next() {
+        Some(c) if matches!(c, '*' | '-' | '_') => c,
+        _ => return false,
+    };
+    indent(line) <= 3
+        && marks.clone().all(|c| c == first)
+        && marks.by_ref().count() >= 3
+        && line.chars().all(|c| c == first || c == ' ')
+}
+
+fn quote_line(line: &str) -> Option<&str> {
+    let rest = line[indent(line)..].strip_prefix('>').filter(|_| indent(line) <= 3)?;
+    Some(rest.strip_prefix(' ').unwrap_or(rest))
+}
+
+// Quoted lines, and the lazy lines that carry on a quoted paragraph
+fn quote(lines: &[String], start: usize) -> (Block, usize) {
+    let mut quoted: Vec<String> = Vec::new();
+    let mut i = start;
+    while i < lines.len() {
+        let line = &lines[i];
+        if let Some(inner) = quote_line(line) {
+            quoted.push(inner.to_string());
+        } else if !is_blank(line) && quoted.last().is_some_and(|last| !is_blank(last)) && !interrupts(line) {
+            quoted.push(line.trim_start().to_string());
+        } else {
+            break;
+        }
+        i += 1;
+    }
+    (Block::Quote(blocks(&quoted)), i)
+}
+
+// A list item marker: a bullet, or a number followed by `.` or `)`
+struct Marker {
+    ordered: bool,
+    symbol: char,
+    start: u64,
+    // The column the item's content starts at
+    content: usize,
+    empty: bool,
+}
+
+impl Marker {
+    fn parse(line: &str) -> Option<Marker> {
+        let column = indent(line);
+        let rest = &line[column..];
+        let digits = rest.chars().take_while(char::is_ascii_digit).count();
+        let (ordered, symbol, start, width) = match rest.chars().next()? {
+            c @ ('-' | '*' | '+') => (false, c, 0, 1),
+            _ if (1..=9).contains(&digits) => {
+                let symbol = rest[digits..].chars().next().filter(|&c| c == '.' || c == ')')?;
+                (true, symbol, rest[..digits].parse().ok()?, digits + 1)
+            }
+            _ => return None,
+        };
+        let after = &rest[width..];
+        if column > 3 || !(after.is_empty() || after.starts_with(' ')) || is_rule(line) {
+            return None;
+        }
+        let empty = is_blank(after);
+        let spaces = if empty || indent(after) > 4 { 1 } else { indent(after) };
+        Some(Marker { ordered, symbol, start, content: column + width + spaces, empty })
+    }
+
+    fn same_list(&self, other: &Marker) -> bool {
+        self.ordered == other.ordered && self.symbol == other.symbol
+    }
+}
+
+fn list(lines: &[String], start: usize) -> (Block, usize) {
+    let first = Marker::parse(&lines[start]).unwrap();
+    let mut items = Vec::new();
+    let mut tight = true;
+    let mut i = start;
+    while let Some(marker) = Marker::parse(&lines[i]).filter(|m| m.same_list(&first)) {
+        let mut item = vec![lines[i].get(marker.content..).unwrap_or("").to_string()];
+        i += 1;
+        while i < lines.len() {
+            let line = &lines[i];
+            let previous_blank = item.last().is_some_and(|last| is_blank(last));
+            if is_blank(line) {
+                // An item can start with at most one blank line
+                if marker.empty && item.len() == 1 {
+                    break;
+                }
+                item.push(String::new());
+            } else if indent(line) >= marker.content {
+                item.push(line[marker.content..].to_string());
+            } else if !previous_blank && !interrupts(line) && Marker::parse(line).is_none() {
+                item.push(line.trim_start().to_string());
+            } else {
+                break;
+            }
+            i += 1;
+        }
+
+        let mut trailing_blanks = 0;
+        while item.last().is_some_and(|last| is_blank(last)) {
+            item.pop();
+            trailing_blanks += 1;
+        }
+        let children = blocks(&item);
+        if children.len() > 1 && item.iter().any(|line| is_blank(line)) {
+            tight = false;
+        }
+        items.push(children);
+
+        let next = lines.get(i).and_then(|line| Marker::parse(line));
+        if !next.is_some_and(|next| next.same_list(&first)) {
+            break;
+        }
+        if trailing_blanks > 0 {
+            tight = false;
+        }
+    }
+    let start = first.ordered.then_some(first.start);
+    (Block::List { start, tight, items }, i)
+}
+
+// An HTML block runs until the next blank line
+fn html_start(line: &str) -> bool {
+    let rest = &line[indent(line)..];
+    let mut chars = rest.chars();
+    indent(line) <= 3
+        && chars.next() == Some('<')
+        && chars.next().is_some_and(|c| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'))
+}
+
+fn html_block(lines: &[String], start: usize) -> (Block, usize) {
+    let end = lines[start..].iter().position(|line| is_blank(line)).map_or(lines.len(), |n| start + n);
+    (Block::Html(lines[start..end].join("\n")), end)
+}
+
+//
+// Whether `line` starts a block that ends the paragraph above it.
+// Only block-level HTML tags, and lists starting at 1, may do so.
+//
+fn interrupts(line: &str) -> bool {
+    const BLOCK_TAGS: &[&str] = &[
+        "blockquote", "details", "div", "figure", "h1", "h2", "h3", "h4", "h5", "h6", "hr", "ol", "p",
+        "pre", "section", "summary", "table", "ul",
+    ];
+    let html_block_tag = html_start(line) && {
+        let tag: String = line.trim_start()[1..]
+            .trim_start_matches('/')
+            .chars()
+            .take_while(char::is_ascii_alphanumeric)
+            .collect();
+        BLOCK_TAGS.contains(&tag.to_ascii_lowercase().as_str()) || line.trim_start().starts_with("<!--")
+    };
+    let list_start = Marker::parse(line).is_some_and(|m| !m.empty && (!m.ordered || m.start == 1));
+    Fence::open(line).is_some()
+        || atx_heading(line).is_some()
+        || is_rule(line)
+        || quote_line(line).is_some()
+        || html_block_tag
+        || list_start
+}
+
+fn paragraph(lines: &[String], start: usize) -> (Block, usize) {
+    let mut text: Vec<&str> = vec![lines[start].trim_start()];
+    let mut i = start + 1;
+    while i < lines.len() {
+        let line = &lines[i];
+        if is_blank(line) {
+            break;
+        }
+        if let Some(level) = setext_level(line) {
+            let text = inlines(text.join("\n").trim_end());
+            return (Block::Heading { level, text }, i + 1);
+        }
+        if interrupts(line) {
+            break;
+        }
+        text.push(line.trim_start());
+        i += 1;
+    }
+    (Block::Paragraph(inlines(text.join("\n").trim_end())), i)
+}
+
+//
+// The language of a fence: the first word of its info string, or the
+// first class of pandoc-style attributes, as in `{.rust startFrom=17}`
+//
+pub fn language(info: &str) -> Option<&str> {
+    let word = info.split_whitespace().next()?;
+    if word.starts_with('{') {
+        return info
+            .trim_matches(|c| c == '{' || c == '}')
+            .split_whitespace()
+            .find_map(|attribute| attribute.strip_prefix('.'));
+    }
+    Some(word)
+}
+
+// The value of `key=value` in a fence's `{...}` attributes, unquoted
+pub fn attribute(info: &str, key: &str) -> Option<String> {
+    let attributes = info.trim().strip_prefix('{')?.strip_suffix('}')?;
+    let mut rest = attributes;
+    loop {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+        let name = &rest[..end];
+        rest = &rest[end..];
+        let mut value = None;
+        if let Some(after) = rest.strip_prefix('=') {
+            if let Some(quoted) = after.strip_prefix('"') {
+                let mut text = String::new();
+                let mut chars = quoted.char_indices();
+                let mut consumed = quoted.len();
+                while let Some((i, c)) = chars.next() {
+                    match c {
+                        '\\' => text.extend(chars.next().map(|(_, c)| c)),
+                        '"' => {
+                            consumed = i + 1;
+                            break;
+                        }
+                        c => text.push(c),
+                    }
+                }
+                value = Some(text);
+                rest = &quoted[consumed..];
+            } else {
+                let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                value = Some(after[..end].to_string());
+                rest = &after[end..];
+            }
+        }
+        if name == key {
+            return value;
+        }
+    }
+}
+
+//
+// Inline structure
+//
+
+pub fn inlines(text: &str) -> Vec<Inline> {
+    let mut out = Vec::new();
+    let mut plain = String::new();
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < text.len() {
+        let found = match bytes[i] {
+            b'\\' if bytes.get(i + 1) == Some(&b'\n') => Some((Inline::HardBreak, i + 2)),
+            b'\\' if bytes.get(i + 1).is_some_and(u8::is_ascii_punctuation) => {
+                plain.push(bytes[i + 1] as char);
+                i += 2;
+                continue;
+            }
+            b'\n' => {
+                let trailing = plain.len() - plain.trim_end_matches(' ').len();
+                plain.truncate(plain.len() - trailing);
+                let end = i + 1 + indent(&text[i + 1..]);
+                Some((if trailing >= 2 { Inline::HardBreak } else { Inline::SoftBreak }, end))
+            }
+            b'`' => code_span(text, i).or_else(|| literal_run(text, i, &mut plain)),
+            b'<' => autolink(text, i).or_else(|| inline_html(text, i)),
+            b'&' => entity(text, i),
+            b'!' if bytes.get(i + 1) == Some(&b'[') => link(text, i + 1).map(|(url, title, inner, end)| {
+                (Inline::Image { url, title, alt: inlines(inner) }, end)
+            }),
+            b'[' => link(text, i).map(|(url, title, inner, end)| (Inline::Link { url, title, text: inlines(inner) }, end)),
+            b'*' | b'_' => emphasis(text, i, &mut plain).or_else(|| literal_run(text, i, &mut plain)),
+            _ => None,
+        };
+        match found {
+            Some((Inline::Text(_), end)) => i = end,
+            Some((inline, end)) => {
+                if !plain.is_empty() {
+                    out.push(Inline::Text(std::mem::take(&mut plain)));
+                }
+                out.push(inline);
+                i = end;
+            }
+            None => {
+                let c = text[i..].chars().next().unwrap();
+                plain.push(c);
+                i += c.len_utf8();
+            }
+        }
+    }
+    if !plain.is_empty() {
+        out.push(Inline::Text(plain));
+    }
+    out
+}
+
+//
+// A run of backticks or emphasis markers that did not match anything
+// is text, all of it, so that it is not matched piecemeal later
+//
+fn literal_run(text: &str, start: usize, plain: &mut String) -> Option<(Inline, usize)> {
+    let end = run_end(text, start);
+    plain.push_str(&text[start..end]);
+    Some((Inline::Text(String::new()), end))
+}
+
+fn run_end(text: &str, start: usize) -> usize {
+    let c = text.as_bytes()[start];
+    start + text[start..].bytes().take_while(|&b| b == c).count()
+}
+
+fn code_span(text: &str, start: usize) -> Option<(Inline, usize)> {
+    let open = run_end(text, start) - start;
+    let mut i = start + open;
+    while let Some(offset) = text[i..].find('`') {
+        let run = i + offset;
+        let end = run_end(text, run);
+        if end - run == open {
+            let code = text[start + open..run].replace('\n', " ");
+            let code = match code.strip_prefix(' ').and_then(|c| c.strip_suffix(' ')) {
+                Some(inner) if !code.trim().is_empty() => inner.to_string(),
+                _ => code,
+            };
+            return Some((Inline::Code(code), end));
+        }
+        i = end;
+    }
+    None
+}
+
+fn autolink(text: &str, start: usize) -> Option<(Inline, usize)> {
+    let end = start + text[start..].find('>')?;
+    let url = &text[start + 1..end];
+    if url.chars().any(|c| c.is_whitespace() || c == '<') {
+        return None;
+    }
+    let scheme = url.split(':').next().unwrap_or("");
+    let is_uri = url.contains(':')
+        && (2..=32).contains(&scheme.len())
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '.' | '-'));
+    let is_email = !is_uri && url.split('@').count() == 2 && !url.starts_with('@') && !url.ends_with('@');
+    let href = match (is_uri, is_email) {
+        (true, _) => url.to_string(),
+        (_, true) => format!("mailto:{}", url),
+        _ => return None,
+    };
+    Some((Inline::Link { url: href, title: None, text: vec![Inline::Text(url.to_string())] }, end + 1))
+}
+
+fn inline_html(text: &str, start: usize) -> Option<(Inline, usize)> {
+    let rest = &text[start..];
+    let end = if rest.starts_with("<!--") {
+        rest.find("-->")? + 3
+    } else {
+        let name = rest[1..].trim_start_matches('/');
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        rest.find('>')? + 1
+    };
+    Some((Inline::Html(rest[..end].to_string()), start + end))
+}
+
+// `&amp;`, `&#169;` or `&#xA9;`, passed through as they are
+fn entity(text: &str, start: usize) -> Option<(Inline, usize)> {
+    let rest = &text[start + 1..];
+    let end = rest.find(';').filter(|&end| end > 0 && end <= 32)?;
+    let name = rest[..end].strip_prefix('#').map_or(&rest[..end], |n| n.strip_prefix(['x', 'X']).unwrap_or(n));
+    if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return None;
+    }
+    Some((Inline::Html(text[start..start + end + 2].to_string()), start + end + 2))
+}
+
+//
+// `[text](url "title")`, given the position of its `[`: the URL, the
+// title, the text, and where the link ends
+//
+fn link(text: &str, start: usize) -> Option<(String, Option<String>, &str, usize)> {
+    let bytes = text.as_bytes();
+    let mut depth = 0;
+    let mut i = start;
+    let close = loop {
+        match bytes.get(i)? {
+            b'\\' => i += 1,
+            b'`' => i = code_span(text, i).map_or(run_end(text, i), |(_, end)| end) - 1,
+            b'[' => depth += 1,
+            b']' if depth == 1 => break i,
+            b']' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    };
+    let mut i = close + 1;
+    if bytes.get(i) != Some(&b'(') {
+        return None;
+    }
+    i += 1;
+    let skip_spaces = |i: &mut usize| {
+        while bytes.get(*i).is_some_and(u8::is_ascii_whitespace) {
+            *i += 1;
+        }
+    };
+    skip_spaces(&mut i);
+
+    let url_start = i;
+    let url = if bytes.get(i) == Some(&b'<') {
+        let end = i + text[i..].find('>')?;
+        i = end + 1;
+        &text[url_start + 1..end]
+    } else {
+        let mut parens = 0;
+        while let Some(&b) = bytes.get(i) {
+            match b {
+                b'\\' => i += 1,
+                b'(' => parens += 1,
+                b')' if parens == 0 => break,
+                b')' => parens -= 1,
+                _ if b.is_ascii_whitespace() => break,
+                _ => {}
+            }
+            i += 1;
+        }
+        &text[url_start..i.min(text.len())]
+    };
+
+    skip_spaces(&mut i);
+    let mut title = None;
+    if let Some(&quote) = bytes.get(i).filter(|&&b| matches!(b, b'"' | b'\'' | b'(')) {
+        let closing = if quote == b'(' { ')' } else { quote as char };
+        let end = i + 1 + text[i + 1..].find(closing)?;
+        title = Some(unescape(&text[i + 1..end]));
+        i = end + 1;
+        skip_spaces(&mut i);
+    }
+    if bytes.get(i) != Some(&b')') {
+        return None;
+    }
+    Some((unescape(url), title, &text[start + 1..close], i + 1))
+}
+
+fn unescape(text: &str) -> String {
+    let mut out = String::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.peek() {
+            Some(&next) if c == '\\' && next.is_ascii_punctuation() => {
+                out.push(next);
+                chars.next();
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+//
+// Whether the run of `*` or `_` between `start` and `end` can open
+// and close emphasis, from the characters on either side of it
+//
+fn flanking(text: &str, start: usize, end: usize) -> (bool, bool) {
+    let before = text[..start].chars().next_back();
+    let after = text[end..].chars().next();
+    let space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+    let punct = |c: Option<char>| c.is_some_and(|c| c.is_ascii_punctuation());
+    let left = !space(after) && (!punct(after) || space(before) || punct(before));
+    let right = !space(before) && (!punct(before) || space(after) || punct(after));
+    if text.as_bytes()[start] == b'_' {
+        // Underscores inside words, as in snake_case, are not emphasis
+        (left && (!right || punct(before)), right && (!left || punct(after)))
+    } else {
+        (left, right)
+    }
+}
+
+//
+// Emphasis opened by the run of markers at `start`, closed by the
+// next run of the same marker that can close it. The run with fewer
+// markers decides: one is emphasis, two strong, three both. Markers
+// left over at the opening run are text.
+//
+fn emphasis(text: &str, start: usize, plain: &mut String) -> Option<(Inline, usize)> {
+    let marker = text.as_bytes()[start];
+    let open_end = run_end(text, start);
+    if !flanking(text, start, open_end).0 {
+        return None;
+    }
+    let bytes = text.as_bytes();
+    let mut i = open_end;
+    while i < text.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'`' => i = code_span(text, i).map_or(run_end(text, i), |(_, end)| end),
+            b'[' => i = link(text, i).map_or(i + 1, |(.., end)| end),
+            b if b == marker => {
+                let end = run_end(text, i);
+                if flanking(text, i, end).1 {
+                    let used = (open_end - start).min(end - i).min(3);
+                    plain.push_str(&text[start..open_end - used]);
+                    let inner = inlines(&text[open_end..i]);
+                    let inline = match used {
+                        1 => Inline::Emphasis(inner),
+                        2 => Inline::Strong(inner),
+                        _ => Inline::Emphasis(vec![Inline::Strong(inner)]),
+                    };
+                    return Some((inline, i + used));
+                }
+                i = end;
+            }
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(s: &str) -> Inline {
+        Inline::Text(s.to_string())
+    }
+
+    #[test]
+    fn test_headings_and_paragraphs() {
+        let blocks = parse("# Title #\n\nSome\ntext\n\nSetext\n---\n");
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Heading { level: 1, text: vec![text("Title")] },
+                Block::Paragraph(vec![text("Some"), Inline::SoftBreak, text("text")]),
+                Block::Heading { level: 2, text: vec![text("Setext")] },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fenced_code() {
+        let blocks = parse("\n``` rust\nfn main() {\n\n    x\n}\n```\n\n");
+        assert_eq!(
+            blocks,
+            vec![Block::Code { info: String::from("rust"), text: String::from("fn main() {\n\n    x\n}\n") }]
+        );
+        // Never closed
+        assert_eq!(parse("~~~\na"), vec![Block::Code { info: String::new(), text: String::from("a\n") }]);
+    }
+
+    #[test]
+    fn test_fence_attributes() {
+        let info = "{.rust startFrom=17 file=\"src/a b/main.rs\"}";
+        assert_eq!(language(info), Some("rust"));
+        assert_eq!(language("rust"), Some("rust"));
+        assert_eq!(language(""), None);
+        assert_eq!(attribute(info, "startFrom").as_deref(), Some("17"));
+        assert_eq!(attribute(info, "file").as_deref(), Some("src/a b/main.rs"));
+        assert_eq!(attribute(info, "missing"), None);
+        assert_eq!(attribute("rust", "startFrom"), None);
+    }
+
+    #[test]
+    fn test_lists() {
+        let blocks = parse("* one\n* two\n  more\n\n1. a\n\n2. b\n");
+        assert_eq!(
+            blocks,
+            vec![
+                Block::List {
+                    start: None,
+                    tight: true,
+                    items: vec![
+                        vec![Block::Paragraph(vec![text("one")])],
+                        vec![Block::Paragraph(vec![text("two"), Inline::SoftBreak, text("more")])],
+                    ],
+                },
+                Block::List {
+                    start: Some(1),
+                    tight: false,
+                    items: vec![vec![Block::Paragraph(vec![text("a")])], vec![Block::Paragraph(vec![text("b")])]],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quotes_rules_and_html() {
+        let blocks = parse("> quoted\nlazy\n\n***\n<details>\n<summary>Helper</summary>\n\nafter\n");
+        assert_eq!(
+            blocks,
+            vec![
+                Block::Quote(vec![Block::Paragraph(vec![text("quoted"), Inline::SoftBreak, text("lazy")])]),
+                Block::Rule,
+                Block::Html(String::from("<details>\n<summary>Helper</summary>")),
+                Block::Paragraph(vec![text("after")]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_code_spans_and_escapes() {
+        assert_eq!(
+            inlines("a `x`` y` \\*b\\* ``"),
+            vec![text("a "), Inline::Code(String::from("x`` y")), text(" *b* ``")]
+        );
+    }
+
+    #[test]
+    fn test_emphasis() {
+        assert_eq!(
+            inlines("*a* **b** ***c*** test_clone_me _d_"),
+            vec![
+                Inline::Emphasis(vec![text("a")]),
+                text(" "),
+                Inline::Strong(vec![text("b")]),
+                text(" "),
+                Inline::Emphasis(vec![Inline::Strong(vec![text("c")])]),
+                text(" test_clone_me "),
+                Inline::Emphasis(vec![text("d")]),
+            ]
+        );
+        assert_eq!(inlines("2 * 3 * 4"), vec![text("2 * 3 * 4")]);
+    }
+
+    #[test]
+    fn test_links_and_images() {
+        assert_eq!(
+            inlines("see [the *book*](https://doc.rust-lang.org/book/ \"Book\") and ![logo](rust.png)"),
+            vec![
+                text("see "),
+                Inline::Link {
+                    url: String::from("https://doc.rust-lang.org/book/"),
+                    title: Some(String::from("Book")),
+                    text: vec![text("the "), Inline::Emphasis(vec![text("book")])],
+                },
+                text(" and "),
+                Inline::Image { url: String::from("rust.png"), title: None, alt: vec![text("logo")] },
+            ]
+        );
+        assert_eq!(inlines("[not a link]"), vec![text("[not a link]")]);
+    }
+
+    #[test]
+    fn test_autolinks_html_and_breaks() {
+        assert_eq!(
+            inlines("<https://x.org> <b>&amp;</b>  \nnext\\\nlast"),
+            vec![
+                Inline::Link {
+                    url: String::from("https://x.org"),
+                    title: None,
+                    text: vec![text("https://x.org")],
+                },
+                text(" "),
+                Inline::Html(String::from("<b>")),
+                Inline::Html(String::from("&amp;")),
+                Inline::Html(String::from("</b>")),
+                Inline::HardBreak,
+                text("next"),
+                Inline::HardBreak,
+                text("last"),
+            ]
+        );
+        assert_eq!(inlines("a < b && c > d"), vec![text("a < b && c > d")]);
+    }
+}
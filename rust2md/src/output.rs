@@ -0,0 +1,82 @@
+//
+// Generated files are rendered in memory first. They are then either
+// written out, or, with `--check`, compared with what is already on
+// disk.
+//
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::diff;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputFile {
+    pub path: PathBuf,
+    pub contents: Vec<u8>,
+}
+
+impl OutputFile {
+    pub fn new(path: PathBuf, contents: impl Into<Vec<u8>>) -> OutputFile {
+        OutputFile { path, contents: contents.into() }
+    }
+}
+
+//
+// Writes every file whose contents on disk differ from `files`, and
+// returns their paths. Files that are already up to date are left
+// alone, so that whatever watches them is not woken up for nothing.
+//
+pub fn write_all(files: &[OutputFile]) -> Result<Vec<PathBuf>, String> {
+    let mut written = Vec::new();
+    for file in files {
+        if fs::read(&file.path).is_ok_and(|on_disk| on_disk == file.contents) {
+            continue;
+        }
+        if let Some(parent) = file.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("cannot create '{}': {}", parent.display(), e))?;
+        }
+        fs::write(&file.path, &file.contents)
+            .map_err(|e| format!("cannot write '{}': {}", file.path.display(), e))?;
+        written.push(file.path.clone());
+    }
+    Ok(written)
+}
+
+//
+// Prints a unified diff for every file whose contents on disk differ
+// from `files`, and returns the paths of those files
+//
+pub fn check_all(files: &[OutputFile]) -> Vec<PathBuf> {
+    let mut stale = Vec::new();
+    for file in files {
+        if let Some(diff) = check(&file.path, &file.contents) {
+            print!("{}", diff);
+            stale.push(file.path.clone());
+        }
+    }
+    stale
+}
+
+fn check(path: &Path, contents: &[u8]) -> Option<String> {
+    let name = path.display().to_string();
+    let on_disk = match fs::read(path) {
+        Ok(on_disk) if on_disk == contents => return None,
+        Ok(on_disk) => on_disk,
+        Err(_) => {
+            let new = String::from_utf8_lossy(contents);
+            return diff::unified("/dev/null", &name, "", &new);
+        }
+    };
+    match (std::str::from_utf8(&on_disk), std::str::from_utf8(contents)) {
+        (Ok(old), Ok(new)) => diff::unified(&name, &name, old, new),
+        _ => Some(format!("Binary files {} and {} (generated) differ\n", name, name)),
+    }
+}
+
+//
+// The error that ends a `--check` run that found `stale` files
+//
+pub fn stale_failure(stale: &[PathBuf], command: &str) -> String {
+    let files = if stale.len() == 1 { "file is" } else { "files are" };
+    format!("{} generated {} out of date; run '{}' to update", stale.len(), files, command)
+}
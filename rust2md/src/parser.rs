@@ -0,0 +1,599 @@
+//
+// The line-by-line parser behind rust2md. It is fed one line at a
+// time and keeps track of whether it is inside prose or code, and of
+// the column the last comment started at, which is where code is
+// dedented to. The lexer decides what counts as a comment line.
+//
+use crate::compile_fail::CompileFail;
+use crate::diagnostics::Diagnostic;
+use crate::document::{Block, Document, Include, Prose, Snippet};
+use crate::indent::{self, Dedented};
+use crate::region::{Region, RegionLine};
+use crate::lexer::{self, Lexer, LineKind, Token, TokenKind};
+use crate::{BlockComments, DocComments, Options};
+
+// What a comment starts with to be a directive rather than prose
+const DIRECTIVE: &str = "rust2md:";
+// The comments around a region of code that can be included elsewhere
+const REGION: &str = "region:";
+const END_REGION: &str = "endregion";
+
+#[derive(Default)]
+pub struct Parser {
+    options: Options,
+    lexer: Lexer,
+    // The column of the `/*! ... */` comment being read as prose
+    prose_comment: Option<usize>,
+    started_parsing: bool,
+    start_of_comment_at: usize,
+    parsing_code: bool,
+    line_number: usize,
+    // Where the comment or string the lexer is inside of started
+    open_since: Option<(usize, &'static str)>,
+    scopes: Scopes,
+    last_heading: Option<usize>,
+    // A `// rust2md: full` directive waiting for the next snippet
+    full: bool,
+    // Where the `// rust2md: hide-start` being obeyed is
+    hide_start: Option<usize>,
+    // The item after a `hide-next` or `collapse` directive
+    item: Option<Item>,
+    // Whether the blank lines after a collapsed item are left out
+    after_collapse: bool,
+    // The regions being read, innermost last
+    regions: Vec<Region>,
+    // A `compile-fail` directive waiting for the comment after it
+    compile_fail: Option<CompileFail>,
+    // The column of the comment being read as a compile-fail example
+    failing_code: Option<usize>,
+    document: Document,
+}
+
+//
+// The item a directive applies to: the code from the directive on,
+// until a line that is neither blank, an attribute nor a comment
+// leaves the braces as they were
+//
+struct Item {
+    depth: usize,
+    // The summary of the `<details>` block the item goes into, or
+    // `None` if it is hidden
+    collapse: Option<String>,
+}
+
+//
+// The braces opened by the code shown so far, each labelled with the
+// item it belongs to (`fn test_x`, `impl Spaceship`...) when there is
+// one, and the line it was opened on
+//
+#[derive(Default)]
+struct Scopes {
+    open: Vec<(Option<String>, usize)>,
+    keyword: Option<String>,
+    label: Option<String>,
+}
+
+impl Scopes {
+    fn depth(&self) -> usize {
+        self.open.len()
+    }
+
+    fn line(&mut self, line: &str, tokens: &[Token], line_number: usize) {
+        for token in tokens {
+            let text = token.text(line);
+            match token.kind {
+                TokenKind::Ident if self.keyword.is_some() && self.label.is_none() => {
+                    self.label = Some(format!("{} {}", self.keyword.take().unwrap(), text));
+                }
+                TokenKind::Ident if matches!(text, "fn" | "struct" | "enum" | "impl" | "mod" | "trait") => {
+                    self.keyword = Some(text.to_string());
+                    self.label = None;
+                }
+                TokenKind::Punct if text == "{" => {
+                    self.keyword = None;
+                    self.open.push((self.label.take(), line_number));
+                }
+                TokenKind::Punct if text == "}" => {
+                    // Closing a scope opened before the first comment
+                    self.open.pop();
+                }
+                TokenKind::Punct if text == ";" => {
+                    self.keyword = None;
+                    self.label = None;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // The innermost labelled scope that is still open
+    fn innermost(&self) -> Option<(&str, usize)> {
+        self.open.iter().rev().find_map(|(label, line)| label.as_deref().map(|label| (label, *line)))
+    }
+}
+
+impl Parser {
+    pub fn new(options: &Options) -> Parser {
+        Parser { options: options.clone(), ..Parser::default() }
+    }
+
+    pub fn line(&mut self, line: &str) {
+        self.line_number += 1;
+        let line = if self.line_number == 1 {
+            line.strip_prefix('\u{feff}').unwrap_or(line)
+        } else {
+            line
+        };
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let tokens = self.lexer.line(line);
+        match (self.lexer.open(), self.open_since) {
+            (Some(what), None) => self.open_since = Some((self.line_number, what)),
+            (None, Some(_)) => self.open_since = None,
+            _ => {}
+        }
+        if let Some(column) = self.prose_comment {
+            self.prose_comment_line(line, &tokens[0], column, false);
+            return;
+        }
+        if let Some(column) = self.failing_code {
+            self.failing_code_line(line, &tokens[0], column, false);
+            return;
+        }
+        let kind = lexer::classify(&tokens);
+        if self.compile_fail.is_some() && !matches!(kind, LineKind::Blank | LineKind::BlockComment) {
+            self.warn(String::from("compile-fail must be followed by a /* */ comment holding the example"));
+            self.compile_fail = None;
+        }
+        match kind {
+            LineKind::BlockComment if self.compile_fail.is_some() => {
+                let first = tokens.iter().find(|t| t.kind.is_comment()).unwrap();
+                let column = self.column_of(line, first.start);
+                // The example is a snippet of its own
+                self.parsing_code = false;
+                self.failing_code_line(line, first, column, true);
+            }
+            //
+            // A `///` comment documents the item below it. Unless it is
+            // lifted out as prose, it belongs in the snippet with it.
+            //
+            LineKind::Comment { kind: TokenKind::OuterDoc, .. }
+                if self.options.doc_comments == DocComments::Attach =>
+            {
+                self.code_line_if_started(line, &tokens);
+            }
+            //
+            // `// rust2md: ...` comments tell rust2md what to do, rather
+            // than being part of the text
+            //
+            LineKind::Comment { kind: TokenKind::LineComment, column }
+                if comment_text(&line[column..], TokenKind::LineComment).starts_with(DIRECTIVE) =>
+            {
+                let text = comment_text(&line[column..], TokenKind::LineComment);
+                self.directive(text[DIRECTIVE.len()..].trim());
+            }
+            LineKind::Comment { kind: TokenKind::LineComment, column }
+                if is_region_marker(comment_text(&line[column..], TokenKind::LineComment)) =>
+            {
+                let text = comment_text(&line[column..], TokenKind::LineComment);
+                self.region_marker(text.trim_end());
+            }
+            //
+            // We found a line holding nothing but a // comment (or a
+            // //! comment, which documents the chapter itself), which
+            // we assume to contain markdown text
+            //
+            LineKind::Comment { kind, column } => {
+                self.start_prose(self.column_of(line, column));
+                let text = comment_text(&line[column..], kind);
+                self.prose(text.to_string());
+            }
+            LineKind::BlockComment if self.options.block_comments == BlockComments::Prose => {
+                let first = tokens.iter().find(|t| t.kind.is_comment()).unwrap();
+                if first.kind == TokenKind::InnerBlockDoc && first.text(line).starts_with("/*!") {
+                    let column = self.column_of(line, first.start);
+                    self.start_prose(column);
+                    self.prose_comment_line(line, first, column, true);
+                } else {
+                    self.code_line_if_started(line, &tokens);
+                }
+            }
+            _ => self.code_line_if_started(line, &tokens),
+        }
+    }
+
+    // Code is ignored until the first comment appears
+    fn code_line_if_started(&mut self, line: &str, tokens: &[Token]) {
+        if self.started_parsing {
+            self.code_line(line, tokens);
+        }
+    }
+
+    // The column at which the byte `offset` of `line` is displayed
+    fn column_of(&self, line: &str, offset: usize) -> usize {
+        indent::width(&line[..offset], self.options.tab_width)
+    }
+
+    fn start_prose(&mut self, column: usize) {
+        //
+        // Prose in the middle of an item cuts its code in two, one
+        // half in each fence around the prose
+        //
+        if self.parsing_code {
+            if let Some((label, _)) = self.scopes.innermost() {
+                let message = format!("fence split inside {}", label);
+                self.warn(message);
+            }
+        }
+        self.parsing_code = false;
+        self.started_parsing = true;
+        self.start_of_comment_at = column;
+        // Directives about the next item do not reach past prose
+        self.item = None;
+        self.after_collapse = false;
+    }
+
+    //
+    // One line of a `/*! ... */` comment rendered as prose. The
+    // delimiters, and any `*` running down the left of the comment,
+    // are left out, as are the lines that only held a delimiter.
+    //
+    fn prose_comment_line(&mut self, line: &str, token: &Token, column: usize, first: bool) {
+        let closed = !self.lexer.in_block_comment();
+        let mut text = token.text(line);
+        if first {
+            text = &text[3..];
+        }
+        if closed {
+            text = text.strip_suffix("*/").unwrap_or(text);
+        }
+        self.prose_comment = if closed { None } else { Some(column) };
+
+        let dedented;
+        if !first {
+            dedented = match indent::dedent(text, column, self.options.tab_width) {
+                Dedented::Line(text) | Dedented::Short(text) => text,
+                Dedented::Blank => String::new(),
+            };
+            text = &dedented;
+            let undecorated = text.trim_start().strip_prefix('*').filter(|rest| rest.is_empty() || rest.starts_with(' '));
+            text = undecorated.unwrap_or(text);
+        }
+        let text = text.strip_prefix(' ').unwrap_or(text).trim_end();
+        if !text.is_empty() || !(first || closed) {
+            self.prose(text.to_string());
+        }
+
+        //
+        // Code may follow the end of the comment on the same line. It
+        // keeps its columns, with the comment blanked out.
+        //
+        if closed && !line[token.end..].trim().is_empty() {
+            let code = format!("{}{}", " ".repeat(self.column_of(line, token.end)), &line[token.end..]);
+            let tokens = Lexer::new().line(&code);
+            self.code_line(&code, &tokens);
+        }
+    }
+
+    //
+    // One line of the comment holding a compile-fail example, which is
+    // code once the delimiters are left out
+    //
+    fn failing_code_line(&mut self, line: &str, token: &Token, column: usize, first: bool) {
+        let closed = !self.lexer.in_block_comment();
+        let mut text = token.text(line);
+        if first {
+            text = text[2..].trim_start();
+        }
+        if closed {
+            text = text.strip_suffix("*/").unwrap_or(text);
+        }
+        self.failing_code = if closed { None } else { Some(column) };
+        let text = match indent::dedent(text, if first { 0 } else { column }, self.options.tab_width) {
+            Dedented::Line(text) | Dedented::Short(text) => text.trim_end().to_string(),
+            Dedented::Blank => String::new(),
+        };
+        //
+        // The example is shown, but is not code of the chapter, so it
+        // goes in no region
+        //
+        if !text.is_empty() || (self.parsing_code && !closed) {
+            self.code_block().lines.push(text);
+        }
+        if closed {
+            // The code after the example is a snippet of its own
+            self.parsing_code = false;
+            self.compile_fail = None;
+        }
+    }
+
+    fn code_line(&mut self, line: &str, tokens: &[Token]) {
+        self.scopes.line(line, tokens, self.line_number);
+        let stripped;
+        let mut line = line;
+        if self.options.block_comments == BlockComments::Strip && tokens.iter().any(|t| is_block_comment(t.kind)) {
+            stripped = strip_block_comments(line, tokens);
+            //
+            // A line that held nothing but a block comment goes
+            // altogether, rather than opening a snippet or leaving
+            // a blank line behind
+            //
+            if stripped.trim().is_empty() {
+                return;
+            }
+            line = &stripped;
+        }
+        if !self.hidden(line) {
+            self.code_line_text(line);
+        }
+    }
+
+    fn code_line_text(&mut self, line: &str) {
+        //
+        // If we haven't found a comment, then we assume we
+        // are embedding a code snippet
+        //
+        match indent::dedent(line, self.start_of_comment_at, self.options.tab_width) {
+            Dedented::Line(code) => self.code(code),
+            Dedented::Blank => self.code(String::new()),
+            //
+            // Code should start in the same column as the last comment.
+            // A line that only closes a scope opened before the prose,
+            // such as the `}` of `mod tests`, belongs to code that was
+            // never shown, so it is left out. Any other code is kept, as
+            // far left as it goes, but the layout of the snippet is
+            // probably off.
+            //
+            Dedented::Short(code) if code.trim_end().chars().all(|c| matches!(c, '}' | ')' | ']' | ';' | ',')) => {
+                if !self.parsing_code {
+                    self.code_block();
+                }
+            }
+            Dedented::Short(code) => {
+                self.warn(format!(
+                    "code is indented less than the comment above it (column {}): {}",
+                    self.start_of_comment_at + 1,
+                    code.trim_end()
+                ));
+                self.code(code);
+            }
+        }
+    }
+
+    pub fn finish(mut self) -> Document {
+        if let Some((line, what)) = self.open_since {
+            let message = format!("{} opened at line {} is never closed", what, line);
+            self.warn(message);
+        }
+        if let Some((label, line)) = self.scopes.innermost() {
+            let message = format!("{} opened at line {} is never closed", label, line);
+            self.warn(message);
+        }
+        if let Some(line) = self.hide_start {
+            let message = format!("hide-start at line {} is never ended", line);
+            self.warn(message);
+        }
+        for region in std::mem::take(&mut self.regions) {
+            let message = format!("region '{}' at line {} is never ended", region.name, region.line);
+            self.warn(message);
+            self.document.regions.push(region);
+        }
+        self.document.lines = self.line_number;
+        self.document
+    }
+
+    fn warn(&mut self, message: String) {
+        self.document.diagnostics.push(Diagnostic::new(self.line_number, message));
+    }
+
+    //
+    // Headings should go down one level at a time, or the table of
+    // contents ends up with holes in it
+    //
+    fn check_heading(&mut self, text: &str) {
+        let level = text.chars().take_while(|&c| c == '#').count();
+        if level == 0 || level > 6 || !(text[level..].is_empty() || text[level..].starts_with(' ')) {
+            return;
+        }
+        if let Some(last) = self.last_heading {
+            if level > last + 1 {
+                let message = format!("heading level jumps from {} to {}", "#".repeat(last), "#".repeat(level));
+                self.warn(message);
+            }
+        }
+        self.last_heading = Some(level);
+    }
+
+    //
+    // Directives change how code is shown, never what is compiled:
+    //
+    //     full                  show the snippet the code after it goes
+    //                           into whole, test scaffolding included
+    //     hide-start, hide-end  leave out the code between them
+    //     hide-next             leave out the next item
+    //     collapse "Summary"    show the next item folded away, in a
+    //                           `<details>` block of its own
+    //     include chapter#name  show the code of a region (see `region`)
+    //     compile-fail E0000    show the /* */ comment after it as an
+    //                           example that does not compile (see
+    //                           `compile_fail`)
+    //
+    fn directive(&mut self, directive: &str) {
+        let (name, argument) = directive.split_once(char::is_whitespace).unwrap_or((directive, ""));
+        let argument = argument.trim();
+        match name {
+            "full" => self.full = true,
+            "hide-start" if self.hide_start.is_some() => self.warn(String::from("hide-start inside hidden code")),
+            "hide-start" => self.hide_start = Some(self.line_number),
+            "hide-end" if self.hide_start.is_none() => self.warn(String::from("hide-end without a hide-start")),
+            "hide-end" => self.hide_start = None,
+            "include" if argument.is_empty() => self.warn(String::from("include needs a region, as in data_types#spaceship")),
+            "include" => {
+                for region in &mut self.regions {
+                    region.lines.push(RegionLine::Include(argument.to_string()));
+                }
+                self.document.blocks.push(Block::Include(Include { target: argument.to_string(), line: self.line_number }));
+                // The code after it goes into a snippet of its own
+                self.parsing_code = false;
+            }
+            "compile-fail" => {
+                let code = Some(argument.to_string()).filter(|code| !code.is_empty());
+                self.compile_fail = Some(CompileFail { code, errors: None });
+            }
+            "hide-next" => self.item = Some(Item { depth: self.scopes.depth(), collapse: None }),
+            "collapse" => {
+                let summary = argument.strip_prefix('"').and_then(|a| a.strip_suffix('"')).unwrap_or(argument);
+                let summary = if summary.is_empty() { "Code" } else { summary };
+                self.item = Some(Item { depth: self.scopes.depth(), collapse: Some(summary.to_string()) });
+                // The item gets a snippet of its own
+                self.parsing_code = false;
+            }
+            _ => self.warn(format!("unknown directive '{}'", directive)),
+        }
+    }
+
+    fn region_marker(&mut self, text: &str) {
+        if text == END_REGION {
+            match self.regions.pop() {
+                Some(region) => self.document.regions.push(region),
+                None => self.warn(String::from("endregion without a region")),
+            }
+            return;
+        }
+        let name = text[REGION.len()..].trim();
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '#') {
+            self.warn(format!("invalid region name '{}'", name));
+        } else if self.regions.iter().chain(&self.document.regions).any(|r| r.name == name) {
+            self.warn(format!("region '{}' is defined twice", name));
+        }
+        self.regions.push(Region { name: name.to_string(), line: self.line_number, first_line: self.line_number + 1, lines: Vec::new() });
+    }
+
+    //
+    // Whether a code line is left out by a directive, keeping track of
+    // where the item it is part of ends
+    //
+    fn hidden(&mut self, line: &str) -> bool {
+        if self.hide_start.is_some() {
+            return true;
+        }
+        let item = match &self.item {
+            Some(item) => item,
+            None => return false,
+        };
+        let trimmed = line.trim();
+        let lead_in = ["#[", "//", "/*"].iter().any(|start| trimmed.starts_with(start));
+        let over = self.scopes.depth() == item.depth && !(trimmed.is_empty() || lead_in);
+        let hidden = item.collapse.is_none();
+        // A collapsed item starts with its code, not with blank lines
+        if !hidden && trimmed.is_empty() && !self.parsing_code {
+            return true;
+        }
+        if over {
+            self.item = None;
+        }
+        if !hidden {
+            self.code_line_text(line);
+            if over {
+                self.parsing_code = false;
+                self.after_collapse = true;
+            }
+        }
+        true
+    }
+
+    fn prose(&mut self, text: String) {
+        self.check_heading(&text);
+        if let Some(Block::Prose(prose)) = self.document.blocks.last_mut() {
+            prose.lines.push(text);
+            return;
+        }
+        self.document.blocks.push(Block::Prose(Prose {
+            lines: vec![text],
+            first_line: self.line_number,
+        }));
+    }
+
+    fn code(&mut self, text: String) {
+        if self.after_collapse && !self.parsing_code && text.is_empty() {
+            return;
+        }
+        self.after_collapse = false;
+        for region in &mut self.regions {
+            if region.lines.is_empty() {
+                region.first_line = self.line_number;
+            }
+            region.lines.push(RegionLine::Code(text.clone()));
+        }
+        self.code_block().lines.push(text);
+    }
+
+    //
+    // The snippet currently being filled, opening a new one if the
+    // last block was prose
+    //
+    fn code_block(&mut self) -> &mut Snippet {
+        if !self.parsing_code {
+            self.parsing_code = true;
+            self.document.blocks.push(Block::Code(Snippet {
+                lines: Vec::new(),
+                first_line: self.line_number,
+                summary: self.item.as_ref().and_then(|item| item.collapse.clone()),
+                compile_fail: self.compile_fail.take(),
+                ..Snippet::default()
+            }));
+        }
+        let full = std::mem::take(&mut self.full);
+        match self.document.blocks.last_mut() {
+            Some(Block::Code(snippet)) => {
+                snippet.full |= full;
+                snippet
+            }
+            _ => unreachable!("a snippet was just opened"),
+        }
+    }
+}
+
+//
+// The markdown inside a `// ...` comment: what follows the slashes
+// (and the `/` or `!` of a doc comment), less the single space that
+// separates them from the text
+//
+fn comment_text(comment: &str, kind: TokenKind) -> &str {
+    let text = match kind {
+        TokenKind::OuterDoc | TokenKind::InnerDoc => &comment[3..],
+        _ => &comment[2..],
+    };
+    text.strip_prefix(' ').unwrap_or(text)
+}
+
+fn is_region_marker(text: &str) -> bool {
+    text.starts_with(REGION) || text.trim_end() == END_REGION
+}
+
+fn is_block_comment(kind: TokenKind) -> bool {
+    matches!(kind, TokenKind::BlockComment | TokenKind::OuterBlockDoc | TokenKind::InnerBlockDoc)
+}
+
+//
+// The line without its block comments, and without the whitespace
+// that separated them from the code
+//
+fn strip_block_comments(line: &str, tokens: &[Token]) -> String {
+    let mut stripped = String::new();
+    let mut skip_whitespace = false;
+    for token in tokens {
+        if is_block_comment(token.kind) {
+            if stripped.trim().is_empty() {
+                skip_whitespace = true;
+            } else {
+                stripped.truncate(stripped.trim_end().len());
+            }
+            continue;
+        }
+        if !(skip_whitespace && token.kind == TokenKind::Whitespace) {
+            stripped.push_str(token.text(line));
+        }
+        skip_whitespace = false;
+    }
+    stripped
+}
@@ -0,0 +1,183 @@
+//
+// Named regions of code, shown again wherever they are included, so
+// that a definition such as `Spaceship` is written once:
+//
+//     // region: spaceship
+//     struct Spaceship {
+//         fuel: u8,
+//     }
+//     // endregion
+//
+// and then, in any chapter:
+//
+//     // rust2md: include data_types#spaceship
+//
+// `#spaceship` (or `spaceship`) on its own names a region of the same
+// chapter. A region may include others, as long as none ends up
+// including itself. Includes only change what is shown: the code
+// still has to compile where it is.
+//
+use crate::diagnostics::Diagnostic;
+use crate::document::{Block, Document, Snippet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    pub name: String,
+    // The line of its `region:` directive
+    pub line: usize,
+    // The line its code starts on
+    pub first_line: usize,
+    pub lines: Vec<RegionLine>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegionLine {
+    Code(String),
+    // A region included in this one
+    Include(String),
+}
+
+//
+// A document that can be included from, with the name other chapters
+// know it by and the file it came from
+//
+pub struct Chapter<'a> {
+    pub name: &'a str,
+    pub file: Option<&'a str>,
+    pub document: &'a Document,
+}
+
+//
+// Replaces every include in `documents` with a snippet of the code it
+// names. Includes that cannot be resolved are left out, and reported
+// for the document they are in.
+//
+pub fn include(documents: &mut [Document], names: &[&str], files: &[Option<&str>]) -> Vec<Vec<Diagnostic>> {
+    let resolved: Vec<Vec<(usize, Result<Snippet, String>)>> = {
+        let chapters: Vec<Chapter> = documents
+            .iter()
+            .zip(names)
+            .zip(files)
+            .map(|((document, name), file)| Chapter { name, file: *file, document })
+            .collect();
+        chapters
+            .iter()
+            .map(|chapter| {
+                let includes = chapter.document.blocks.iter().enumerate().filter_map(|(n, block)| match block {
+                    Block::Include(include) => Some((n, include)),
+                    _ => None,
+                });
+                includes.map(|(n, include)| (n, snippet(&chapters, chapter.name, &include.target))).collect()
+            })
+            .collect()
+    };
+
+    let mut problems = Vec::new();
+    for (document, resolved) in documents.iter_mut().zip(resolved) {
+        let mut diagnostics = Vec::new();
+        for (n, snippet) in resolved {
+            if let Block::Include(include) = &document.blocks[n] {
+                match snippet {
+                    Ok(snippet) => document.blocks[n] = Block::Code(snippet),
+                    Err(message) => diagnostics.push(Diagnostic::new(include.line, message)),
+                }
+            }
+        }
+        problems.push(diagnostics);
+    }
+    problems
+}
+
+// The snippet a target included from chapter `from` stands for
+fn snippet(chapters: &[Chapter], from: &str, target: &str) -> Result<Snippet, String> {
+    let (chapter, region) = find(chapters, from, target)?;
+    let mut lines = Vec::new();
+    let mut path = vec![format!("{}#{}", chapter.name, region.name)];
+    expand(chapters, chapter, region, &mut path, &mut lines)?;
+    Ok(Snippet {
+        lines,
+        first_line: region.first_line,
+        file: chapter.file.map(String::from),
+        ..Snippet::default()
+    })
+}
+
+fn find<'a>(chapters: &'a [Chapter<'a>], from: &str, target: &str) -> Result<(&'a Chapter<'a>, &'a Region), String> {
+    let (chapter, name) = match target.split_once('#') {
+        Some(("", name)) => (from, name),
+        Some((chapter, name)) => (chapter, name),
+        None => (from, target),
+    };
+    let chapter = chapters
+        .iter()
+        .find(|c| c.name == chapter)
+        .ok_or_else(|| format!("cannot include '{}': no chapter named '{}'", target, chapter))?;
+    let region = chapter
+        .document
+        .regions
+        .iter()
+        .find(|r| r.name == name)
+        .ok_or_else(|| format!("cannot include '{}': no region '{}' in chapter '{}'", target, name, chapter.name))?;
+    Ok((chapter, region))
+}
+
+//
+// The code of a region, with the regions it includes in place. `path`
+// holds the regions being expanded, outermost first, to catch cycles.
+//
+fn expand(chapters: &[Chapter], chapter: &Chapter, region: &Region, path: &mut Vec<String>, lines: &mut Vec<String>) -> Result<(), String> {
+    for line in &region.lines {
+        match line {
+            RegionLine::Code(code) => lines.push(code.clone()),
+            RegionLine::Include(target) => {
+                let (inner_chapter, inner) = find(chapters, chapter.name, target)?;
+                let name = format!("{}#{}", inner_chapter.name, inner.name);
+                if path.contains(&name) {
+                    path.push(name);
+                    return Err(format!("include cycle: {}", path.join(" -> ")));
+                }
+                path.push(name);
+                expand(chapters, inner_chapter, inner, path, lines)?;
+                path.pop();
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse, Options};
+
+    fn documents(sources: &[&str]) -> Vec<Document> {
+        sources.iter().map(|source| parse(source.as_bytes(), &Options::default()).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_regions_are_included() {
+        let mut documents = documents(&[
+            "// Text\n// region: ship\nstruct Ship;\n// endregion\n",
+            "// Text\n// rust2md: include a#ship\n// rust2md: include a#plane\n",
+        ]);
+        let problems = include(&mut documents, &["a", "b"], &[Some("a.rs"), None]);
+        assert_eq!(problems[0], []);
+        assert_eq!(problems[1], [Diagnostic::new(3, String::from("cannot include 'a#plane': no region 'plane' in chapter 'a'"))]);
+        let snippets: Vec<&Snippet> = documents[1].snippets().collect();
+        assert_eq!(snippets.len(), 1);
+        assert_eq!(snippets[0].lines, ["struct Ship;"]);
+        assert_eq!(snippets[0].first_line, 3);
+        assert_eq!(snippets[0].file.as_deref(), Some("a.rs"));
+    }
+
+    #[test]
+    fn test_cycles_are_reported() {
+        let mut documents = documents(&[
+            "// Text\n// region: x\n// rust2md: include b#y\n// endregion\n",
+            "// Text\n// region: y\n// rust2md: include a#x\n// endregion\n// rust2md: include y\n",
+        ]);
+        let problems = include(&mut documents, &["a", "b"], &[None, None]);
+        assert_eq!(problems[0], [Diagnostic::new(3, String::from("include cycle: b#y -> a#x -> b#y"))]);
+        assert_eq!(problems[1][1], Diagnostic::new(5, String::from("include cycle: b#y -> a#x -> b#y")));
+    }
+}
@@ -0,0 +1,164 @@
+//
+// Test scaffolding, which `Scaffolding::Strip` takes out of snippets.
+// The chapters are made of tests such as
+//
+//     #[test]
+//     fn test_booleans() {
+//         assert_eq!(true, !false);
+//     }
+//
+// but what they teach is the assertions, so only the body is shown,
+// dedented:
+//
+//     assert_eq!(true, !false);
+//
+// Everything else is left as it is: helper functions, structs, and
+// tests with other attributes (such as `#[should_panic]`), arguments
+// or a return type, whose signature says something.
+//
+use crate::indent::{self, Dedented};
+use crate::lexer::{Lexer, TokenKind};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scaffolding {
+    // Snippets show the tests whole
+    #[default]
+    Keep,
+    // Snippets show the bodies of the tests only
+    Strip,
+}
+
+impl Scaffolding {
+    pub fn parse(name: &str) -> Option<Scaffolding> {
+        match name {
+            "keep" => Some(Scaffolding::Keep),
+            "strip" => Some(Scaffolding::Strip),
+            _ => None,
+        }
+    }
+}
+
+//
+// The lines of a snippet with its tests unwrapped, each with the index
+// of the line of `lines` it came from. The blank lines that keep a
+// body apart from the code around it come from none.
+//
+pub fn strip(lines: &[String], tab_width: usize) -> Vec<(Option<usize>, String)> {
+    let depths = depths(lines);
+    let mut out: Vec<(Option<usize>, String)> = Vec::new();
+    let mut n = 0;
+    while n < lines.len() {
+        let end = if depths[n] == 0 { test_end(lines, &depths, n) } else { None };
+        let end = match end {
+            Some(end) => end,
+            None => {
+                out.push((Some(n), lines[n].clone()));
+                n += 1;
+                continue;
+            }
+        };
+        // A body is kept apart from the code around it
+        if out.last().is_some_and(|(_, line)| !line.trim().is_empty()) {
+            out.push((None, String::new()));
+        }
+        let body = &lines[n + 2..end];
+        let column = body
+            .iter()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| indent::width(&line[..line.len() - line.trim_start().len()], tab_width))
+            .min()
+            .unwrap_or(0);
+        for (offset, line) in body.iter().enumerate() {
+            let text = match indent::dedent(line, column, tab_width) {
+                Dedented::Line(text) | Dedented::Short(text) => text,
+                Dedented::Blank => String::new(),
+            };
+            out.push((Some(n + 2 + offset), text));
+        }
+        n = end + 1;
+        if lines.get(n).is_some_and(|line| !line.trim().is_empty()) {
+            out.push((None, String::new()));
+        }
+    }
+    out
+}
+
+//
+// The brace depth at the start of each line, counting from the start
+// of the snippet, and at the end of the last one
+//
+fn depths(lines: &[String]) -> Vec<usize> {
+    let mut lexer = Lexer::new();
+    let mut depth: usize = 0;
+    let mut depths = Vec::with_capacity(lines.len() + 1);
+    for line in lines {
+        depths.push(depth);
+        for token in lexer.line(line) {
+            match (token.kind, token.text(line)) {
+                (TokenKind::Punct, "{") => depth += 1,
+                (TokenKind::Punct, "}") => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+    depths.push(depth);
+    depths
+}
+
+//
+// The line of the closing brace of the test starting at line `n`, if
+// there is one: `#[test]`, then `fn name() {` on the next line, and
+// a `}` of its own that brings the depth back to where it was
+//
+fn test_end(lines: &[String], depths: &[usize], n: usize) -> Option<usize> {
+    if lines[n].trim() != "#[test]" {
+        return None;
+    }
+    let signature = lines.get(n + 1)?;
+    let tokens: Vec<&str> = Lexer::new()
+        .line(signature)
+        .iter()
+        .filter(|t| t.kind != TokenKind::Whitespace)
+        .map(|t| t.text(signature))
+        .collect();
+    if !matches!(tokens.as_slice(), ["fn", _, "(", ")", "{"]) {
+        return None;
+    }
+    let end = (n + 2..lines.len()).find(|&end| depths[end + 1] == depths[n])?;
+    if lines[end].trim() != "}" || depths[end] != depths[n] + 1 {
+        return None;
+    }
+    Some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strip_text(text: &str) -> String {
+        let lines: Vec<String> = text.lines().map(String::from).collect();
+        let stripped: Vec<String> = strip(&lines, 4).into_iter().map(|(_, line)| line).collect();
+        stripped.join("\n")
+    }
+
+    #[test]
+    fn test_tests_are_unwrapped() {
+        assert_eq!(
+            strip_text("#[test]\nfn test_a() {\n    let a = 1;\n\n    assert_eq!(a, 1);\n}\n#[test]\nfn test_b() {\n    if true {\n        f();\n    }\n}"),
+            "let a = 1;\n\nassert_eq!(a, 1);\n\nif true {\n    f();\n}"
+        );
+    }
+
+    #[test]
+    fn test_lines_know_where_they_came_from() {
+        let lines: Vec<String> = ["let a = 1;", "#[test]", "fn test_a() {", "    assert_eq!(a, 1);", "}"].map(String::from).to_vec();
+        assert_eq!(strip(&lines, 4), [(Some(0), String::from("let a = 1;")), (None, String::new()), (Some(3), String::from("assert_eq!(a, 1);"))]);
+    }
+
+    #[test]
+    fn test_everything_else_is_kept() {
+        let text = "struct Spaceship {\n    fuel: u8,\n}\nfn check_not_empty(s: String) {\n    assert!(!s.is_empty());\n}\n\
+                    #[test]\n#[should_panic]\nfn test_panics() {\n    f();\n}\n#[test]\nfn test_open() {\n    f(); }";
+        assert_eq!(strip_text(text), text);
+    }
+}
@@ -0,0 +1,247 @@
+//
+// `rust2md serve`: watch mode (see `watch`), with the book served on
+// localhost as it is rebuilt. Every HTML page gets a small script that
+// listens for server-sent events: `reload` once a build succeeds, and
+// `failed`, with the error, once one fails, which the page shows over
+// itself. The files on disk are left as they are.
+//
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::book::{self, BookOptions};
+use crate::manifest::{Format, Manifest};
+use crate::watch;
+
+pub const DEFAULT_PORT: u16 = 3000;
+const LIVE_RELOAD: &str = include_str!("live.js");
+const SCRIPT_PATH: &str = "/__rust2md/live.js";
+const EVENTS_PATH: &str = "/__rust2md/events";
+// How often an idle event stream says something, to find out whether
+// the page is still there
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+//
+// The outcome of the last build, and how many there have been, which
+// is what tells event streams that there is news
+//
+#[derive(Debug, Default)]
+struct Build {
+    count: u64,
+    error: Option<String>,
+}
+
+type Builds = Arc<(Mutex<Build>, Condvar)>;
+
+pub fn run(options: &BookOptions, port: u16) -> Result<(), String> {
+    let manifest = Manifest::load(&options.root)?;
+    if !manifest.as_ref().is_some_and(|m| m.output.formats.contains(&Format::Html)) {
+        eprintln!("rust2md: warning: \"html\" is not among the manifest's output formats, so there are no pages to serve");
+    }
+    let root = book::output_dir(options, manifest.as_ref());
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("cannot listen on port {}: {}", port, e))?;
+    println!("Serving '{}' at http://localhost:{}/", root.display(), port);
+
+    let builds: Builds = Arc::default();
+    let shared = Arc::clone(&builds);
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let (root, builds) = (root.clone(), Arc::clone(&shared));
+            thread::spawn(move || respond(stream, &root, &builds));
+        }
+    });
+    watch::watch(options, |result| {
+        let (build, changed) = &*builds;
+        let mut build = build.lock().unwrap_or_else(PoisonError::into_inner);
+        build.count += 1;
+        build.error = result.as_ref().err().cloned();
+        changed.notify_all();
+    })
+}
+
+fn respond(stream: TcpStream, root: &Path, builds: &Builds) {
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    // The headers say nothing that matters here
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 0) && !header.trim().is_empty() {
+        header.clear();
+    }
+    let mut parts = request.split_whitespace();
+    let (method, target) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let stream = &stream;
+    if method != "GET" && method != "HEAD" {
+        let _ = reply(stream, "405 Method Not Allowed", "text/plain", b"Method not allowed\n", false);
+        return;
+    }
+    let head = method == "HEAD";
+    if target == EVENTS_PATH {
+        events(stream, builds);
+        return;
+    }
+    if target == SCRIPT_PATH {
+        let _ = reply(stream, "200 OK", "text/javascript; charset=utf-8", LIVE_RELOAD.as_bytes(), head);
+        return;
+    }
+    let file = match resolve(root, target) {
+        Some(file) => file,
+        None => {
+            let _ = reply(stream, "404 Not Found", "text/plain", b"Not found\n", head);
+            return;
+        }
+    };
+    let _ = match fs::read(&file) {
+        Ok(contents) => {
+            let content_type = content_type(&file);
+            let contents = if content_type.starts_with("text/html") { with_live_reload(&contents) } else { contents };
+            reply(stream, "200 OK", content_type, &contents, head)
+        }
+        Err(_) => reply(stream, "404 Not Found", "text/plain", b"Not found\n", head),
+    };
+}
+
+fn reply(mut stream: &TcpStream, status: &str, content_type: &str, body: &[u8], head: bool) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    if !head {
+        stream.write_all(body)?;
+    }
+    stream.flush()
+}
+
+//
+// An event stream that lasts as long as the page: `reload` after each
+// good build, `failed` after each bad one. A page that connects after
+// a bad build is told about it straight away.
+//
+fn events(mut stream: &TcpStream, builds: &Builds) {
+    let headers = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\n\r\n";
+    if stream.write_all(headers.as_bytes()).is_err() {
+        return;
+    }
+    let (lock, changed) = &**builds;
+    let build = lock.lock().unwrap_or_else(PoisonError::into_inner);
+    let mut seen = build.count;
+    let mut event = build.error.as_deref().map(failed);
+    drop(build);
+    loop {
+        let text = event.take().unwrap_or_else(|| String::from(": keep-alive\n\n"));
+        if stream.write_all(text.as_bytes()).and_then(|_| stream.flush()).is_err() {
+            return;
+        }
+        let build = lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let (build, _) = changed.wait_timeout_while(build, KEEP_ALIVE, |build| build.count == seen).unwrap_or_else(PoisonError::into_inner);
+        if build.count != seen {
+            seen = build.count;
+            event = Some(build.error.as_deref().map_or_else(|| String::from("event: reload\ndata:\n\n"), failed));
+        }
+    }
+}
+
+// A `failed` event, with one `data:` line for each line of the error
+fn failed(error: &str) -> String {
+    let mut event = String::from("event: failed\n");
+    for line in error.lines() {
+        event.push_str(&format!("data: {}\n", line));
+    }
+    event.push('\n');
+    event
+}
+
+//
+// The file a request is for, below `root`: `/` and directories stand
+// for their `index.html`. Paths that would lead out of `root` lead
+// nowhere.
+//
+fn resolve(root: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next()?;
+    let path = decode(path.strip_prefix('/')?)?;
+    let mut file = root.to_path_buf();
+    for component in Path::new(&path).components() {
+        match component {
+            Component::Normal(part) => file.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if file.is_dir() {
+        file.push("index.html");
+    }
+    Some(file)
+}
+
+// A path with its `%XX` escapes decoded
+fn decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = path.get(i + 1..i + 3)?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|e| e.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        _ => "application/octet-stream",
+    }
+}
+
+// A page with the live reload script at the end of its body
+fn with_live_reload(page: &[u8]) -> Vec<u8> {
+    let page = String::from_utf8_lossy(page);
+    let script = format!("<script src=\"{}\"></script>\n", SCRIPT_PATH);
+    match page.rfind("</body>") {
+        Some(end) => format!("{}{}{}", &page[..end], script, &page[end..]).into_bytes(),
+        None => format!("{}{}", page, script).into_bytes(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let root = Path::new("/nowhere/docs");
+        assert_eq!(resolve(root, "/borrowing.html?x=1#clone"), Some(root.join("borrowing.html")));
+        assert_eq!(resolve(root, "/a%20b/./c.html"), Some(root.join("a b/c.html")));
+        assert_eq!(resolve(root, "/../secret"), None);
+        assert_eq!(resolve(root, "/%2e%2e/secret"), None);
+        assert_eq!(resolve(root, "http://localhost/"), None);
+    }
+
+    #[test]
+    fn test_pages_get_the_script() {
+        let script = "<script src=\"/__rust2md/live.js\"></script>\n";
+        assert_eq!(with_live_reload(b"<body>\n<p>x</p>\n</body>\n"), format!("<body>\n<p>x</p>\n{}</body>\n", script).into_bytes());
+        assert_eq!(failed("1 failing test\nsee above"), "event: failed\ndata: 1 failing test\ndata: see above\n\n");
+    }
+}
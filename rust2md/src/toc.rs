@@ -0,0 +1,250 @@
+//
+// The book's table of contents. The headings of every chapter are
+// collected in reading order and given anchors that are unique across
+// the whole book, so that a heading has the same anchor on its
+// chapter's page as on the page holding the whole book. The headings
+// nest into a tree, which is written out as markdown, as HTML (see
+// `html::toc`) and as JSON.
+//
+use std::collections::HashSet;
+
+use crate::json;
+use crate::markdown::{Block, Inline};
+
+// Headings down to this level make it into the markdown and HTML
+// tables of contents. The JSON one has them all.
+pub const DEPTH: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Heading {
+    pub level: usize,
+    pub text: String,
+    pub anchor: String,
+    // The name of the chapter the heading is in
+    pub chapter: String,
+}
+
+//
+// Hands out anchors, adding `-1`, `-2`... to the ones already taken
+//
+#[derive(Debug, Default)]
+pub struct Anchors {
+    taken: HashSet<String>,
+}
+
+impl Anchors {
+    pub fn anchor(&mut self, text: &str) -> String {
+        let slug = slug(text);
+        let mut anchor = slug.clone();
+        let mut n = 0;
+        while self.taken.contains(&anchor) {
+            n += 1;
+            anchor = format!("{}-{}", slug, n);
+        }
+        self.taken.insert(anchor.clone());
+        anchor
+    }
+}
+
+//
+// A heading's anchor, as pandoc makes it: lower case, letters, digits
+// and `_-.` only, words joined by `-`, starting with a letter
+//
+pub fn slug(text: &str) -> String {
+    let kept: String = text
+        .chars()
+        .filter(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.') || c.is_whitespace())
+        .collect();
+    let slug = kept.split_whitespace().collect::<Vec<_>>().join("-").to_lowercase();
+    let slug = slug.trim_start_matches(|c: char| !c.is_alphabetic());
+    if slug.is_empty() {
+        String::from("section")
+    } else {
+        slug.to_string()
+    }
+}
+
+//
+// The headings of a chapter, in the order they are rendered, nested
+// ones (in quotes and lists) included
+//
+pub fn headings(blocks: &[Block], chapter: &str, anchors: &mut Anchors) -> Vec<Heading> {
+    let mut headings = Vec::new();
+    collect(blocks, chapter, anchors, &mut headings);
+    headings
+}
+
+fn collect(blocks: &[Block], chapter: &str, anchors: &mut Anchors, headings: &mut Vec<Heading>) {
+    for block in blocks {
+        match block {
+            Block::Heading { level, text } => {
+                let text = plain(text);
+                headings.push(Heading {
+                    level: *level,
+                    anchor: anchors.anchor(&text),
+                    text,
+                    chapter: chapter.to_string(),
+                });
+            }
+            Block::Quote(blocks) => collect(blocks, chapter, anchors, headings),
+            Block::List { items, .. } => {
+                for item in items {
+                    collect(item, chapter, anchors, headings);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// The text of some inlines, without any markup
+pub fn plain(inlines: &[Inline]) -> String {
+    let mut out = String::new();
+    for inline in inlines {
+        match inline {
+            Inline::Text(text) | Inline::Code(text) => out.push_str(text),
+            Inline::Emphasis(inner) | Inline::Strong(inner) => out.push_str(&plain(inner)),
+            Inline::Link { text: inner, .. } | Inline::Image { alt: inner, .. } => out.push_str(&plain(inner)),
+            Inline::Html(_) => {}
+            Inline::SoftBreak | Inline::HardBreak => out.push(' '),
+        }
+    }
+    out
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Entry<'a> {
+    pub heading: &'a Heading,
+    pub children: Vec<Entry<'a>>,
+}
+
+//
+// The headings nested by level. A heading that skips levels is the
+// child of the last shallower one.
+//
+pub fn tree<'a>(headings: &[&'a Heading]) -> Vec<Entry<'a>> {
+    let mut entries = Vec::new();
+    let mut i = 0;
+    while i < headings.len() {
+        let heading = headings[i];
+        let end = i + 1 + headings[i + 1..].iter().take_while(|h| h.level > heading.level).count();
+        entries.push(Entry { heading, children: tree(&headings[i + 1..end]) });
+        i = end;
+    }
+    entries
+}
+
+// The headings down to `depth`, as a tree
+pub fn shallow(headings: &[Heading], depth: usize) -> Vec<Entry<'_>> {
+    let headings: Vec<&Heading> = headings.iter().filter(|h| h.level <= depth).collect();
+    tree(&headings)
+}
+
+//
+// The table of contents as a markdown list, each heading linking to
+// the anchor given by `link`
+//
+pub fn markdown(entries: &[Entry], link: &dyn Fn(&Heading) -> String) -> String {
+    let mut out = String::new();
+    markdown_list(entries, link, 0, &mut out);
+    out
+}
+
+fn markdown_list(entries: &[Entry], link: &dyn Fn(&Heading) -> String, depth: usize, out: &mut String) {
+    for entry in entries {
+        let text = entry.heading.text.replace('\\', "\\\\").replace('[', "\\[").replace(']', "\\]");
+        out.push_str(&format!("{}- [{}]({})\n", "  ".repeat(depth), text, link(entry.heading)));
+        markdown_list(&entry.children, link, depth + 1, out);
+    }
+}
+
+//
+// The table of contents as JSON: the book's title, and for each
+// chapter, its name, whether it is a draft, and its headings as a
+// tree, each with its level, text and anchor
+//
+pub fn json(title: Option<&str>, chapters: &[(&str, bool, &[Heading])]) -> String {
+    let mut out = String::from("{\n");
+    out.push_str(&format!("  \"title\": {},\n", title.map_or_else(|| String::from("null"), json::string)));
+    out.push_str("  \"chapters\": [");
+    for (n, (name, draft, headings)) in chapters.iter().enumerate() {
+        out.push_str(if n == 0 { "\n" } else { ",\n" });
+        out.push_str(&format!("    {{\n      \"name\": {},\n      \"draft\": {},\n", json::string(name), draft));
+        out.push_str("      \"headings\": ");
+        json_list(&shallow(headings, 6), 3, &mut out);
+        out.push_str("\n    }");
+    }
+    out.push_str(if chapters.is_empty() { "]\n}\n" } else { "\n  ]\n}\n" });
+    out
+}
+
+fn json_list(entries: &[Entry], indent: usize, out: &mut String) {
+    if entries.is_empty() {
+        out.push_str("[]");
+        return;
+    }
+    let pad = "  ".repeat(indent);
+    out.push('[');
+    for (n, entry) in entries.iter().enumerate() {
+        out.push_str(if n == 0 { "\n" } else { ",\n" });
+        let heading = entry.heading;
+        out.push_str(&format!(
+            "{}  {{\"level\": {}, \"text\": {}, \"anchor\": {}, \"children\": ",
+            pad,
+            heading.level,
+            json::string(&heading.text),
+            json::string(&heading.anchor)
+        ));
+        json_list(&entry.children, indent + 1, out);
+        out.push('}');
+    }
+    out.push_str(&format!("\n{}]", pad));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::markdown;
+
+    fn book(chapters: &[(&str, &str)]) -> Vec<Heading> {
+        let mut anchors = Anchors::default();
+        chapters
+            .iter()
+            .flat_map(|(name, text)| headings(&markdown::parse(text), name, &mut anchors))
+            .collect()
+    }
+
+    #[test]
+    fn test_slugs() {
+        assert_eq!(slug("For Loop (Range)"), "for-loop-range");
+        assert_eq!(slug("1. `u8` Integers!"), "u8-integers");
+        assert_eq!(slug("???"), "section");
+    }
+
+    #[test]
+    fn test_anchors_are_unique_across_chapters() {
+        let headings = book(&[("a", "# Tests\n## Tests-1\n"), ("b", "# Tests\n## Tests\n")]);
+        let anchors: Vec<&str> = headings.iter().map(|h| h.anchor.as_str()).collect();
+        assert_eq!(anchors, ["tests", "tests-1", "tests-2", "tests-3"]);
+        assert_eq!(headings[2].chapter, "b");
+    }
+
+    #[test]
+    fn test_markdown() {
+        let headings = book(&[("a", "# A\n## B\n#### Too deep\n## C\n"), ("b", "# D\n")]);
+        let toc = markdown(&shallow(&headings, DEPTH), &|h| format!("{}.md#{}", h.chapter, h.anchor));
+        assert_eq!(toc, "- [A](a.md#a)\n  - [B](a.md#b)\n  - [C](a.md#c)\n- [D](b.md#d)\n");
+    }
+
+    #[test]
+    fn test_json() {
+        let headings = book(&[("a", "# A \"1\"\n## B\n")]);
+        assert_eq!(
+            json(Some("Book"), &[("a", false, &headings), ("b", true, &[])]),
+            "{\n  \"title\": \"Book\",\n  \"chapters\": [\n    {\n      \"name\": \"a\",\n      \"draft\": false,\n      \
+             \"headings\": [\n        {\"level\": 1, \"text\": \"A \\\"1\\\"\", \"anchor\": \"a-1\", \"children\": [\n          \
+             {\"level\": 2, \"text\": \"B\", \"anchor\": \"b\", \"children\": []}\n        ]}\n      ]\n    },\n    {\n      \
+             \"name\": \"b\",\n      \"draft\": true,\n      \"headings\": []\n    }\n  ]\n}\n"
+        );
+    }
+}
@@ -0,0 +1,421 @@
+//
+// A reader for the subset of TOML used by `rust2md.toml`: tables,
+// arrays of tables, strings, integers, booleans and (possibly
+// multi-line) arrays. Dotted keys, dates and inline tables are not
+// supported.
+//
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+pub type Table = BTreeMap<String, Value>;
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Boolean(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(t) => Some(t),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse(text: &str) -> Result<Table, String> {
+    let mut parser = Parser { chars: text.chars().collect(), pos: 0, line: 1 };
+    parser.document()
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+//
+// Where `key = value` pairs currently go: the root table, a named
+// `[table]`, or the last element of an `[[array]]`
+//
+enum Target {
+    Table(Vec<String>),
+    ArrayElement(Vec<String>),
+}
+
+impl Parser {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("line {}: {}", self.line, message))
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.bump();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.bump();
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        if self.peek() == Some('#') {
+            while !matches!(self.peek(), None | Some('\n')) {
+                self.bump();
+            }
+        }
+    }
+
+    // Whitespace, newlines and comments, as allowed inside arrays
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_spaces();
+            self.skip_comment();
+            match self.peek() {
+                Some('\n') | Some('\r') => {
+                    self.bump();
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        self.skip_comment();
+        self.eat('\r');
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(&format!("unexpected '{}'", c)),
+        }
+    }
+
+    fn document(&mut self) -> Result<Table, String> {
+        let mut root = Table::new();
+        let mut target = Target::Table(Vec::new());
+        // The tables given a `[header]` so far, which TOML allows once.
+        // Those below an array of tables are forgotten when the array
+        // gets another element, as they belong to the last one.
+        let mut defined: HashSet<Vec<String>> = HashSet::new();
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => return Ok(root),
+                Some('[') => {
+                    // Errors are about the header, not the line after it
+                    let line = self.line;
+                    self.bump();
+                    let is_array = self.eat('[');
+                    let path = self.table_path()?;
+                    if !self.eat(']') || (is_array && !self.eat(']')) {
+                        return self.error("unterminated table header");
+                    }
+                    self.end_of_line()?;
+                    if is_array {
+                        let (last, parents) = path.split_last().unwrap();
+                        let parent = table_at(&mut root, parents).map_err(|e| format!("line {}: {}", line, e))?;
+                        let entry = parent.entry(last.clone()).or_insert_with(|| Value::Array(Vec::new()));
+                        match entry {
+                            Value::Array(items) => items.push(Value::Table(Table::new())),
+                            _ => return Err(format!("line {}: '{}' is not an array of tables", line, last)),
+                        }
+                        defined.retain(|table| !table.starts_with(&path));
+                        target = Target::ArrayElement(path);
+                    } else {
+                        table_at(&mut root, &path).map_err(|e| format!("line {}: {}", line, e))?;
+                        if !defined.insert(path.clone()) {
+                            return Err(format!("line {}: table '{}' is defined twice", line, path.join(".")));
+                        }
+                        target = Target::Table(path);
+                    }
+                }
+                Some(_) => {
+                    let line = self.line;
+                    let key = self.key()?;
+                    self.skip_spaces();
+                    if !self.eat('=') {
+                        return self.error(&format!("expected '=' after '{}'", key));
+                    }
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    self.end_of_line()?;
+                    let table = match &target {
+                        Target::Table(path) => table_at(&mut root, path),
+                        Target::ArrayElement(path) => last_array_element(&mut root, path),
+                    }
+                    .map_err(|e| format!("line {}: {}", line, e))?;
+                    if table.insert(key.clone(), value).is_some() {
+                        return Err(format!("line {}: duplicate key '{}'", line, key));
+                    }
+                }
+            }
+        }
+    }
+
+    fn table_path(&mut self) -> Result<Vec<String>, String> {
+        let mut path = Vec::new();
+        loop {
+            self.skip_spaces();
+            path.push(self.key()?);
+            self.skip_spaces();
+            if !self.eat('.') {
+                return Ok(path);
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let mut key = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                        key.push(c);
+                        self.bump();
+                    } else {
+                        break;
+                    }
+                }
+                if key.is_empty() {
+                    return self.error("expected a key");
+                }
+                Ok(key)
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => {
+                self.bump();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_blank();
+                    if self.eat(']') {
+                        return Ok(Value::Array(items));
+                    }
+                    items.push(self.value()?);
+                    self.skip_blank();
+                    if !self.eat(',') {
+                        self.skip_blank();
+                        if self.eat(']') {
+                            return Ok(Value::Array(items));
+                        }
+                        return self.error("expected ',' or ']' in array");
+                    }
+                }
+            }
+            Some(c) if c == '-' || c == '+' || c.is_ascii_digit() => {
+                let mut number = String::new();
+                while let Some(c) = self.peek() {
+                    if c.is_ascii_digit() || c == '-' || c == '+' || c == '_' {
+                        if c != '_' {
+                            number.push(c);
+                        }
+                        self.bump();
+                    } else {
+                        break;
+                    }
+                }
+                match number.parse() {
+                    Ok(n) => Ok(Value::Integer(n)),
+                    Err(_) => self.error(&format!("invalid number '{}'", number)),
+                }
+            }
+            _ => {
+                let word = self.key()?;
+                match word.as_str() {
+                    "true" => Ok(Value::Boolean(true)),
+                    "false" => Ok(Value::Boolean(false)),
+                    _ => self.error(&format!("unsupported value '{}'", word)),
+                }
+            }
+        }
+    }
+
+    // The next character of a string, which ends at the end of the
+    // line. The newline is left for the error to be about the line the
+    // string is on.
+    fn string_char(&mut self) -> Result<char, String> {
+        match self.peek() {
+            None | Some('\n') => self.error("unterminated string"),
+            Some(c) => {
+                self.bump();
+                Ok(c)
+            }
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.string_char()? {
+                '"' => return Ok(s),
+                '\\' => match self.string_char()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    c => return self.error(&format!("unsupported escape '\\{}'", c)),
+                },
+                c => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.bump();
+        let mut s = String::new();
+        loop {
+            match self.string_char()? {
+                '\'' => return Ok(s),
+                c => s.push(c),
+            }
+        }
+    }
+}
+
+fn table_at<'a>(root: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    let mut table = root;
+    for key in path {
+        let entry = table.entry(key.clone()).or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(t) => t,
+            Value::Array(items) => match items.last_mut() {
+                Some(Value::Table(t)) => t,
+                _ => return Err(format!("'{}' is not a table", key)),
+            },
+            _ => return Err(format!("'{}' is not a table", key)),
+        };
+    }
+    Ok(table)
+}
+
+fn last_array_element<'a>(root: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    let (last, parents) = path.split_last().unwrap();
+    match table_at(root, parents)?.get_mut(last) {
+        Some(Value::Array(items)) => match items.last_mut() {
+            Some(Value::Table(t)) => Ok(t),
+            _ => Err(format!("'{}' is not an array of tables", last)),
+        },
+        _ => Err(format!("'{}' is not an array of tables", last)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_values() {
+        let table = parse(
+            "# A comment\n\
+             name = \"a \\\"quoted\\\" \\\\ name\\n\" # and another\n\
+             path = 'C:\\no\\escapes'\n\
+             count = -1_000\n\
+             draft = true\r\n\
+             ready = false\n\
+             list = [\n  \"a\", # first\n  'b',\n]\n\
+             \"quoted key\" = []\n",
+        )
+        .unwrap();
+        assert_eq!(table["name"], string("a \"quoted\" \\ name\n"));
+        assert_eq!(table["path"], string("C:\\no\\escapes"));
+        assert_eq!(table["count"], Value::Integer(-1000));
+        assert_eq!(table["draft"].as_bool(), Some(true));
+        assert_eq!(table["ready"].as_bool(), Some(false));
+        assert_eq!(table["list"], Value::Array(vec![string("a"), string("b")]));
+        assert_eq!(table["quoted key"], Value::Array(Vec::new()));
+    }
+
+    #[test]
+    fn test_tables() {
+        let table = parse(
+            "top = 1\n[book]\ntitle = \"T\"\n[a.b]\nc = 2\n[a]\nd = 3\n\
+             [[chapter]]\npath = \"x\"\n[chapter.extra]\n[[chapter]]\npath = \"y\"\n[chapter.extra]\n",
+        )
+        .unwrap();
+        assert_eq!(table["top"], Value::Integer(1));
+        assert_eq!(table["book"].as_table().unwrap()["title"], string("T"));
+        assert_eq!(table["a"].as_table().unwrap()["b"].as_table().unwrap()["c"], Value::Integer(2));
+        assert_eq!(table["a"].as_table().unwrap()["d"], Value::Integer(3));
+        let chapters = table["chapter"].as_array().unwrap();
+        let paths: Vec<&str> = chapters.iter().map(|chapter| chapter.as_table().unwrap()["path"].as_str().unwrap()).collect();
+        assert_eq!(paths, ["x", "y"]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |text: &str| parse(text).err().unwrap_or_default();
+        assert_eq!(error("a = 1\na = 2\n"), "line 2: duplicate key 'a'");
+        assert_eq!(error("a = \"open\n"), "line 1: unterminated string");
+        assert_eq!(error("a = 1\nb = 'open\n"), "line 2: unterminated string");
+        assert_eq!(error("a = \"open\\\n\"\n"), "line 1: unterminated string");
+        assert_eq!(error("a = \"\\q\"\n"), "line 1: unsupported escape '\\q'");
+        assert_eq!(error("a = [1 2]\n"), "line 1: expected ',' or ']' in array");
+        assert_eq!(error("a = yes\n"), "line 1: unsupported value 'yes'");
+        assert_eq!(error("a 1\n"), "line 1: expected '=' after 'a'");
+        assert_eq!(error("a = 1 2\n"), "line 1: unexpected '2'");
+        assert_eq!(error("[book\n"), "line 1: unterminated table header");
+        assert_eq!(error("book = 1\n[[book]]\n"), "line 2: 'book' is not an array of tables");
+        assert_eq!(error("book = 1\n[book]\n"), "line 2: 'book' is not a table");
+        assert_eq!(error("[book]\na = 1\n[output]\n[book]\nb = 2\n"), "line 4: table 'book' is defined twice");
+        assert_eq!(error("[a.b]\n[a.b]\n"), "line 2: table 'a.b' is defined twice");
+    }
+}
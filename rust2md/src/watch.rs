@@ -0,0 +1,355 @@
+//
+// `rust2md watch`: builds the book, then builds it again whenever a
+// file below the root changes. Changes come from the operating system
+// (inotify, on Linux). Elsewhere, or when inotify cannot be used, they
+// are found by looking at every file's size and modification time
+// every `INTERVAL`. A burst of changes, such as an editor saving
+// several files, makes a single build, once things have been quiet for
+// `DEBOUNCE`. Chapters whose inputs are the same as in the last build
+// are not converted again (see `cache`).
+//
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::book::{self, BookOptions};
+use crate::cache::Cache;
+use crate::manifest::Manifest;
+
+// How often files are looked at, when there are no notifications
+const INTERVAL: Duration = Duration::from_millis(200);
+// How long things must be quiet for before a build: several intervals,
+// so that looking at the files once more cannot end a burst too soon
+const DEBOUNCE: Duration = INTERVAL.saturating_mul(3);
+
+pub fn run(options: &BookOptions) -> Result<(), String> {
+    watch(options, |_| {})
+}
+
+//
+// Builds the book for as long as the watch goes on, handing what each
+// build wrote (or why it failed) to `built`
+//
+pub fn watch(options: &BookOptions, mut built: impl FnMut(&Result<Vec<PathBuf>, String>)) -> Result<(), String> {
+    // The book is written below the root, and writing it must not set
+    // off another build
+    let output = book::output_dir(options, Manifest::load(&options.root)?.as_ref());
+    let mut watcher = Watcher::new(&options.root, &[output]);
+    let mut cache = Cache::default();
+    loop {
+        let result = book::build_cached(options, &mut cache);
+        let rebuilt = cache.take_rebuilt();
+        match &result {
+            Ok(written) => println!("{}", summary(&rebuilt, written)),
+            Err(message) => eprintln!("rust2md: {}", message),
+        }
+        built(&result);
+        println!("Waiting for changes. Press Ctrl+C to quit.");
+        watcher.wait();
+    }
+}
+
+// What a build did, as in "rebuilt 1 chapter (src/a/src/main.rs), wrote 3 files"
+fn summary(rebuilt: &[PathBuf], written: &[PathBuf]) -> String {
+    let plural = |n: usize, what: &str| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" });
+    let mut summary = format!("rebuilt {}", plural(rebuilt.len(), "chapter"));
+    if !rebuilt.is_empty() {
+        let sources: Vec<String> = rebuilt.iter().map(|source| source.display().to_string()).collect();
+        summary.push_str(&format!(" ({})", sources.join(", ")));
+    }
+    summary.push_str(&format!(", wrote {}", plural(written.len(), "file")));
+    summary
+}
+
+pub struct Watcher {
+    root: PathBuf,
+    // Directories whose changes do not count, besides `target` and
+    // hidden ones
+    ignored: Vec<PathBuf>,
+    changes: Changes,
+}
+
+// Where changes come from
+enum Changes {
+    #[cfg(target_os = "linux")]
+    Events(inotify::Events),
+    // The size and modification time of every file watched, as they
+    // were when last looked at
+    Polling(BTreeMap<PathBuf, (u64, Option<SystemTime>)>),
+}
+
+impl Watcher {
+    pub fn new(root: &Path, ignored: &[PathBuf]) -> Watcher {
+        #[cfg(target_os = "linux")]
+        match inotify::Events::new() {
+            Ok(events) => return Watcher::with(root, ignored, Changes::Events(events)),
+            Err(e) => falling_back(&e.to_string()),
+        }
+        Watcher::polling(root, ignored)
+    }
+
+    // A watcher that looks at the files, without notifications
+    fn polling(root: &Path, ignored: &[PathBuf]) -> Watcher {
+        Watcher::with(root, ignored, Changes::Polling(BTreeMap::new()))
+    }
+
+    fn with(root: &Path, ignored: &[PathBuf], changes: Changes) -> Watcher {
+        let mut watcher = Watcher {
+            root: root.to_path_buf(),
+            ignored: ignored.iter().map(|path| fs::canonicalize(path).unwrap_or_else(|_| path.clone())).collect(),
+            changes,
+        };
+        watcher.scan();
+        watcher
+    }
+
+    //
+    // Waits for a change, and then for things to be quiet again
+    //
+    pub fn wait(&mut self) {
+        self.changed(None);
+        while self.changed(Some(DEBOUNCE)) {}
+        // Directories may have come and gone
+        self.scan();
+    }
+
+    //
+    // Watches every directory there is now, or, without notifications,
+    // looks at the files as they are now
+    //
+    fn scan(&mut self) {
+        let directories = self.directories();
+        match &mut self.changes {
+            #[cfg(target_os = "linux")]
+            Changes::Events(events) => {
+                if let Some(e) = directories.iter().find_map(|dir| events.add(dir).err().map(|e| format!("'{}': {}", dir.display(), e))) {
+                    self.fall_back(&e);
+                }
+            }
+            Changes::Polling(files) => *files = snapshot(&directories),
+        }
+    }
+
+    // Whether anything changed within `timeout`, waiting forever without one
+    fn changed(&mut self, timeout: Option<Duration>) -> bool {
+        match &mut self.changes {
+            #[cfg(target_os = "linux")]
+            Changes::Events(events) => match events.read(timeout) {
+                Ok(changed) => changed,
+                // A build is the safe thing to do, when it is not known
+                // whether anything changed
+                Err(e) => {
+                    self.fall_back(&e.to_string());
+                    true
+                }
+            },
+            Changes::Polling(_) => self.poll(timeout),
+        }
+    }
+
+    // Gives up on notifications, and looks at the files from then on
+    #[cfg(target_os = "linux")]
+    fn fall_back(&mut self, error: &str) {
+        falling_back(error);
+        self.changes = Changes::Polling(snapshot(&self.directories()));
+    }
+
+    fn poll(&mut self, timeout: Option<Duration>) -> bool {
+        let mut waited = Duration::ZERO;
+        while timeout.is_none_or(|timeout| waited < timeout) {
+            thread::sleep(INTERVAL);
+            waited += INTERVAL;
+            let files = snapshot(&self.directories());
+            if let Changes::Polling(known) = &mut self.changes {
+                if files != *known {
+                    *known = files;
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    //
+    // Every directory below the root that is watched, which leaves out
+    // build output and version control
+    //
+    fn directories(&self) -> Vec<PathBuf> {
+        let mut directories = Vec::new();
+        let mut pending = vec![self.root.clone()];
+        while let Some(dir) = pending.pop() {
+            let ignored = fs::canonicalize(&dir).is_ok_and(|path| self.ignored.contains(&path));
+            let hidden = dir
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name == "target" || (name.starts_with('.') && name.len() > 1));
+            if ignored || (hidden && dir != self.root) {
+                continue;
+            }
+            if let Ok(entries) = fs::read_dir(&dir) {
+                pending.extend(entries.flatten().map(|entry| entry.path()).filter(|path| path.is_dir()));
+            }
+            directories.push(dir);
+        }
+        directories.sort();
+        directories
+    }
+}
+
+// The size and modification time of every file in `directories`
+fn snapshot(directories: &[PathBuf]) -> BTreeMap<PathBuf, (u64, Option<SystemTime>)> {
+    let mut files = BTreeMap::new();
+    for dir in directories {
+        for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+            if let Some(metadata) = entry.metadata().ok().filter(|m| m.is_file()) {
+                files.insert(entry.path(), (metadata.len(), metadata.modified().ok()));
+            }
+        }
+    }
+    files
+}
+
+#[cfg(target_os = "linux")]
+fn falling_back(error: &str) {
+    eprintln!("rust2md: cannot watch for changes ({}), looking at the files every {} ms instead", error, INTERVAL.as_millis());
+}
+
+//
+// The little of inotify(7) that is needed, called through the C
+// library that `std` already links to
+//
+#[cfg(target_os = "linux")]
+mod inotify {
+    use std::ffi::CString;
+    use std::fs::File;
+    use std::io::{self, Read};
+    use std::os::raw::{c_char, c_int, c_short, c_ulong};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::{AsRawFd, FromRawFd};
+    use std::path::Path;
+    use std::time::Duration;
+
+    const IN_CLOEXEC: c_int = 0o2000000;
+    // Files written, created, deleted or renamed
+    const IN_CLOSE_WRITE: u32 = 0x008;
+    const IN_MOVED_FROM: u32 = 0x040;
+    const IN_MOVED_TO: u32 = 0x080;
+    const IN_CREATE: u32 = 0x100;
+    const IN_DELETE: u32 = 0x200;
+    const IN_MODIFY: u32 = 0x002;
+    const POLLIN: c_short = 0x001;
+
+    #[repr(C)]
+    struct PollFd {
+        fd: c_int,
+        events: c_short,
+        revents: c_short,
+    }
+
+    extern "C" {
+        fn inotify_init1(flags: c_int) -> c_int;
+        fn inotify_add_watch(fd: c_int, path: *const c_char, mask: u32) -> c_int;
+        fn poll(fds: *mut PollFd, nfds: c_ulong, timeout: c_int) -> c_int;
+    }
+
+    pub struct Events {
+        file: File,
+    }
+
+    impl Events {
+        pub fn new() -> io::Result<Events> {
+            // SAFETY: takes no pointers; the descriptor is checked before use
+            let fd = unsafe { inotify_init1(IN_CLOEXEC) };
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // SAFETY: `fd` is open, and nothing else owns it
+            Ok(Events { file: unsafe { File::from_raw_fd(fd) } })
+        }
+
+        // Watches `dir`, which may already be watched
+        pub fn add(&mut self, dir: &Path) -> io::Result<()> {
+            let path = CString::new(dir.as_os_str().as_bytes()).map_err(io::Error::other)?;
+            let mask = IN_CLOSE_WRITE | IN_MOVED_FROM | IN_MOVED_TO | IN_CREATE | IN_DELETE | IN_MODIFY;
+            // SAFETY: `path` is a valid C string for the length of the call
+            if unsafe { inotify_add_watch(self.file.as_raw_fd(), path.as_ptr(), mask) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        }
+
+        //
+        // Reads the events that come within `timeout` (or, without one,
+        // waits for the next), and returns whether there were any.
+        // Which files they are about does not matter: the cache tells
+        // what changed.
+        //
+        pub fn read(&mut self, timeout: Option<Duration>) -> io::Result<bool> {
+            let mut fds = PollFd { fd: self.file.as_raw_fd(), events: POLLIN, revents: 0 };
+            let timeout = timeout.map_or(-1, |timeout| timeout.as_millis().min(c_int::MAX as u128) as c_int);
+            loop {
+                // SAFETY: `fds` is one valid `pollfd` for the length of the call
+                let ready = unsafe { poll(&mut fds, 1, timeout) };
+                match ready {
+                    0 => return Ok(false),
+                    n if n > 0 => break,
+                    _ => {
+                        let error = io::Error::last_os_error();
+                        if error.kind() != io::ErrorKind::Interrupted {
+                            return Err(error);
+                        }
+                    }
+                }
+            }
+            let mut buffer = [0; 4096];
+            Ok(self.file.read(&mut buffer)? > 0)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn test_changes_are_noticed() {
+        let root = std::env::temp_dir().join(format!("rust2md-watch-{}", process::id()));
+        for dir in ["src/a/src", "src/a/target", "docs", ".git"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        let mut watcher = Watcher::new(&root, &[root.join("docs")]);
+        let mut polling = Watcher::polling(&root, &[root.join("docs")]);
+        let directories: Vec<PathBuf> = watcher.directories().iter().map(|dir| dir.strip_prefix(&root).unwrap().to_path_buf()).collect();
+        assert_eq!(directories, ["", "src", "src/a", "src/a/src"].map(PathBuf::from));
+        #[cfg(target_os = "linux")]
+        assert!(matches!(watcher.changes, Changes::Events(_)));
+
+        // Both notice a burst of changes, and neither returns before it is over
+        fs::write(root.join("src/a/src/main.rs"), "// A\n").unwrap();
+        thread::scope(|scope| {
+            scope.spawn(|| watcher.wait());
+            scope.spawn(|| polling.wait());
+            thread::sleep(INTERVAL + INTERVAL / 2);
+            fs::write(root.join("src/a/src/main.rs"), "// A\n// More\n").unwrap();
+        });
+        let quiet = [watcher.changed(Some(INTERVAL)), polling.changed(Some(INTERVAL))];
+        let files = match &polling.changes {
+            Changes::Polling(files) => files.keys().map(|file| file.strip_prefix(&root).unwrap().to_path_buf()).collect(),
+            #[cfg(target_os = "linux")]
+            Changes::Events(_) => Vec::new(),
+        };
+        fs::remove_dir_all(&root).unwrap();
+        assert_eq!(quiet, [false, false]);
+        assert_eq!(files, [PathBuf::from("src/a/src/main.rs")]);
+    }
+
+    #[test]
+    fn test_summary() {
+        let rebuilt = [PathBuf::from("src/a/src/main.rs")];
+        assert_eq!(summary(&rebuilt, &[]), "rebuilt 1 chapter (src/a/src/main.rs), wrote 0 files");
+        assert_eq!(summary(&[], &rebuilt), "rebuilt 0 chapters, wrote 1 file");
+    }
+}
@@ -0,0 +1,350 @@
+//
+// Turns HTML into XHTML, as EPUB readers want it. Markdown lets raw
+// HTML through, such as `&nbsp;`, `<br>` or a `<details>` block left
+// open, and an XML parser gives up on all of those. Named entities
+// become the characters they stand for, but for the five XML knows;
+// void elements close themselves; attributes get quoted values; and
+// tags are balanced: a closing tag that closes nothing is shown as
+// text, and whatever is left open is closed at the end. Anything else
+// that would not parse, such as a stray `<` or `&`, or an entity this
+// does not know, is escaped and shows as it was written.
+//
+
+// Elements that never have content, and so close themselves in XHTML
+const VOID: [&str; 13] = ["area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "source", "track", "wbr"];
+
+// The entities XML knows without a DTD
+const XML_ENTITIES: [(&str, char); 5] = [("amp", '&'), ("lt", '<'), ("gt", '>'), ("quot", '"'), ("apos", '\'')];
+
+// The HTML entities likely to be written by hand, and their characters
+const ENTITIES: [(&str, char); 52] = [
+    ("nbsp", '\u{a0}'),
+    ("ensp", '\u{2002}'),
+    ("emsp", '\u{2003}'),
+    ("thinsp", '\u{2009}'),
+    ("shy", '\u{ad}'),
+    ("zwj", '\u{200d}'),
+    ("zwnj", '\u{200c}'),
+    ("copy", '©'),
+    ("reg", '®'),
+    ("trade", '™'),
+    ("sect", '§'),
+    ("para", '¶'),
+    ("deg", '°'),
+    ("plusmn", '±'),
+    ("times", '×'),
+    ("divide", '÷'),
+    ("minus", '−'),
+    ("middot", '·'),
+    ("bull", '•'),
+    ("hellip", '…'),
+    ("ndash", '–'),
+    ("mdash", '—'),
+    ("lsquo", '‘'),
+    ("rsquo", '’'),
+    ("sbquo", '‚'),
+    ("ldquo", '“'),
+    ("rdquo", '”'),
+    ("bdquo", '„'),
+    ("laquo", '«'),
+    ("raquo", '»'),
+    ("lsaquo", '‹'),
+    ("rsaquo", '›'),
+    ("dagger", '†'),
+    ("Dagger", '‡'),
+    ("prime", '′'),
+    ("Prime", '″'),
+    ("cent", '¢'),
+    ("pound", '£'),
+    ("yen", '¥'),
+    ("euro", '€'),
+    ("larr", '←'),
+    ("rarr", '→'),
+    ("uarr", '↑'),
+    ("darr", '↓'),
+    ("harr", '↔'),
+    ("lArr", '⇐'),
+    ("rArr", '⇒'),
+    ("hArr", '⇔'),
+    ("le", '≤'),
+    ("ge", '≥'),
+    ("ne", '≠'),
+    ("infin", '∞'),
+];
+
+pub fn from_html(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut open: Vec<String> = Vec::new();
+    let mut rest = html;
+    while let Some(at) = rest.find(['<', '&']) {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        if rest.starts_with('&') {
+            let (text, len) = entity(rest);
+            out.push_str(&text);
+            rest = &rest[len..];
+        } else if let Some(end) = rest.strip_prefix("<!--").and_then(|comment| comment.find("-->")) {
+            // A comment is dropped if XML would not have it
+            let comment = &rest[4..end + 4];
+            if !comment.contains("--") && !comment.ends_with('-') {
+                out.push_str(&rest[..end + 7]);
+            }
+            rest = &rest[end + 7..];
+        } else if let Some((tag, len)) = Tag::parse(rest) {
+            tag.write(&mut open, &mut out);
+            rest = &rest[len..];
+        } else {
+            out.push_str("&lt;");
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    while let Some(name) = open.pop() {
+        out.push_str(&format!("</{}>", name));
+    }
+    out
+}
+
+//
+// An entity at the start of `text`, as XML takes it, and how much of
+// `text` it was. A `&` that starts no known entity is escaped.
+//
+fn entity(text: &str) -> (String, usize) {
+    let escaped = (String::from("&amp;"), 1);
+    let Some(end) = text[1..].find(';').filter(|&end| end > 0 && end <= 32) else {
+        return escaped;
+    };
+    let name = &text[1..end + 1];
+    let len = end + 2;
+    match character(name) {
+        // XML has no place for the control characters, besides whitespace
+        Some(c) if c.is_control() && !matches!(c, '\t' | '\n' | '\r') => escaped,
+        Some(_) if name.starts_with('#') || XML_ENTITIES.iter().any(|(entity, _)| *entity == name) => (text[..len].to_string(), len),
+        Some(c) => (c.to_string(), len),
+        None => escaped,
+    }
+}
+
+//
+// The character an entity stands for, given its name, what comes
+// between `&` and `;`, such as `nbsp` or `#169`
+//
+pub fn character(name: &str) -> Option<char> {
+    match name.strip_prefix('#') {
+        Some(number) => match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => number.parse().ok(),
+        }
+        .and_then(char::from_u32),
+        None => XML_ENTITIES.iter().chain(&ENTITIES).find(|(entity, _)| *entity == name).map(|&(_, c)| c),
+    }
+}
+
+// Text as it goes in an attribute value between double quotes
+fn attribute_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(at) = rest.find(['&', '<', '"']) {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+        if rest.starts_with('&') {
+            let (text, len) = entity(rest);
+            out.push_str(&text);
+            rest = &rest[len..];
+        } else {
+            out.push_str(if rest.starts_with('<') { "&lt;" } else { "&quot;" });
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+struct Tag {
+    name: String,
+    attributes: Vec<(String, String)>,
+    closing: bool,
+    // Whether it ends in `/>`
+    empty: bool,
+}
+
+impl Tag {
+    //
+    // A tag at the start of `text`, and how long it is, or nothing if
+    // it is not one. Names are lower-cased, as XHTML has them.
+    //
+    fn parse(text: &str) -> Option<(Tag, usize)> {
+        let closing = text[1..].starts_with('/');
+        let mut at = if closing { 2 } else { 1 };
+        let element = xml_name(&text[at..]).filter(|name| name.starts_with(|c: char| c.is_ascii_alphabetic()))?;
+        at += element.len();
+        let mut tag = Tag { name: element.to_ascii_lowercase(), attributes: Vec::new(), closing, empty: false };
+        loop {
+            let rest = &text[at..];
+            let trimmed = rest.trim_start();
+            at += rest.len() - trimmed.len();
+            if trimmed.starts_with('>') {
+                return Some((tag, at + 1));
+            }
+            if trimmed.starts_with("/>") {
+                tag.empty = true;
+                return Some((tag, at + 2));
+            }
+            let attribute = xml_name(trimmed)?;
+            at += attribute.len();
+            let trimmed = text[at..].trim_start();
+            let value = match trimmed.strip_prefix('=') {
+                Some(value) => {
+                    let value = value.trim_start();
+                    let (unquoted, len) = match value.chars().next()? {
+                        quote @ ('"' | '\'') => {
+                            let end = value[1..].find(quote)?;
+                            (&value[1..end + 1], end + 2)
+                        }
+                        _ => {
+                            let end = value.find(|c: char| c.is_whitespace() || c == '>').unwrap_or(value.len());
+                            (&value[..end], end)
+                        }
+                    };
+                    at = text.len() - value.len() + len;
+                    unquoted.to_string()
+                }
+                // `<details open>` is `<details open="open">`
+                None => attribute.to_ascii_lowercase(),
+            };
+            let attribute = attribute.to_ascii_lowercase();
+            if !tag.attributes.iter().any(|(name, _)| *name == attribute) {
+                tag.attributes.push((attribute, value));
+            }
+        }
+    }
+
+    fn write(&self, open: &mut Vec<String>, out: &mut String) {
+        let void = VOID.contains(&self.name.as_str());
+        if self.closing {
+            match open.iter().rposition(|name| *name == self.name) {
+                // Whatever was opened within it, and left open, closes first
+                Some(at) => {
+                    for name in open.drain(at..).rev() {
+                        out.push_str(&format!("</{}>", name));
+                    }
+                }
+                // `</br>` means nothing, and other tags closing nothing are shown as they are
+                None if void => {}
+                None => out.push_str(&format!("&lt;/{}&gt;", self.name)),
+            }
+            return;
+        }
+        out.push('<');
+        out.push_str(&self.name);
+        for (name, value) in &self.attributes {
+            out.push_str(&format!(" {}=\"{}\"", name, attribute_value(value)));
+        }
+        if void || self.empty {
+            out.push_str(" />");
+        } else {
+            out.push('>');
+            open.push(self.name.clone());
+        }
+    }
+}
+
+// The XML name at the start of `text`
+fn xml_name(text: &str) -> Option<&str> {
+    let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))).unwrap_or(text.len());
+    let name = &text[..end];
+    (!name.is_empty() && !name.starts_with(|c: char| c.is_ascii_digit() || matches!(c, '-' | '.'))).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html::Renderer;
+    use crate::markdown;
+    use crate::toc::{self, Anchors};
+
+    //
+    // Whether `text` parses as the content of an XML element: tags that
+    // nest, quoted attributes, comments, and only the entities XML knows
+    //
+    fn parses_as_xml(text: &str) -> Result<(), String> {
+        let mut open: Vec<&str> = Vec::new();
+        let mut rest = text;
+        while let Some(at) = rest.find(['<', '&', '>']) {
+            rest = &rest[at..];
+            if let Some(entity) = rest.strip_prefix('&') {
+                let end = entity.find(';').ok_or("entity without ';'")?;
+                let name = &entity[..end];
+                let numeric = name.strip_prefix("#x").map_or_else(
+                    || name.strip_prefix('#').is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit())),
+                    |n| !n.is_empty() && n.chars().all(|c| c.is_ascii_hexdigit()),
+                );
+                if !numeric && !XML_ENTITIES.iter().any(|(entity, _)| *entity == name) {
+                    return Err(format!("undefined entity '&{};'", name));
+                }
+                rest = &entity[end + 1..];
+            } else if rest.starts_with('>') {
+                rest = &rest[1..];
+            } else if let Some(comment) = rest.strip_prefix("<!--") {
+                let end = comment.find("-->").ok_or("unterminated comment")?;
+                if comment[..end].contains("--") {
+                    return Err(String::from("'--' in a comment"));
+                }
+                rest = &comment[end + 3..];
+            } else {
+                let end = rest.find('>').ok_or("unterminated tag")?;
+                let tag = &rest[1..end];
+                rest = &rest[end + 1..];
+                if let Some(name) = tag.strip_prefix('/') {
+                    if open.pop() != Some(name) {
+                        return Err(format!("'</{}>' closes nothing", name));
+                    }
+                    continue;
+                }
+                let (tag, empty) = tag.strip_suffix('/').map_or((tag, false), |tag| (tag, true));
+                let mut parts = tag.split_whitespace();
+                let name = parts.next().filter(|name| xml_name(name) == Some(name)).ok_or(format!("bad tag '<{}>'", tag))?;
+                for attribute in parts {
+                    let (key, value) = attribute.split_once('=').ok_or(format!("attribute '{}' without a value", attribute))?;
+                    let quoted = value.len() >= 2 && value.starts_with('"') && value.ends_with('"');
+                    if xml_name(key) != Some(key) || !quoted || value[1..value.len() - 1].contains(['<', '"']) {
+                        return Err(format!("bad attribute '{}'", attribute));
+                    }
+                }
+                if !empty {
+                    open.push(name);
+                }
+            }
+        }
+        match open.last() {
+            Some(name) => Err(format!("'<{}>' is never closed", name)),
+            None => Ok(()),
+        }
+    }
+
+    #[test]
+    fn test_prose() {
+        let text = "# Moves\n\nOne&nbsp;two<br>three &bogus; AT&T &amp; &#169;\n\n<details open>\n<summary>A <b>bold</summary>\n\nHidden<hr>\n";
+        let blocks = markdown::parse(text);
+        let headings = toc::headings(&blocks, "a", &mut Anchors::default());
+        let html = Renderer::new(&headings).blocks(&blocks);
+        let xhtml = Renderer::new(&headings).xhtml().blocks(&blocks);
+        assert!(parses_as_xml(&html).is_err());
+        assert_eq!(parses_as_xml(&xhtml), Ok(()));
+        assert_eq!(
+            xhtml,
+            "<h1 id=\"moves\">Moves</h1>\n\
+             <p>One\u{a0}two<br />three &amp;bogus; AT&amp;T &amp; &#169;</p>\n\
+             <details open=\"open\">\n<summary>A <b>bold</b></summary>\n\
+             <p>Hidden<hr /></p>\n</details>"
+        );
+    }
+
+    #[test]
+    fn test_tags() {
+        assert_eq!(from_html("<IMG SRC=a.png alt='x \"y\"' alt=z>"), "<img src=\"a.png\" alt=\"x &quot;y&quot;\" />");
+        assert_eq!(from_html("a < b </div> <br></br> <div/>"), "a &lt; b &lt;/div&gt; <br /> <div />");
+        assert_eq!(from_html("<!-- ok --><!-- not -- ok -->&#0;"), "<!-- ok -->&amp;#0;");
+        assert_eq!(from_html("<p><em>x</p>"), "<p><em>x</em></p>");
+    }
+}
@@ -0,0 +1,294 @@
+//
+// Cross-references between chapters. Prose can link to any part of
+// the book with `[[...]]`:
+//
+//     [[data_types]]           a chapter
+//     [[data_types#slices]]    a heading in a chapter
+//     [[slices]]               a heading anywhere in the book
+//     [[test_clone]]           the section showing `fn test_clone`
+//     [[slices|slices]]        any of the above, with its own text
+//
+// Once every chapter has been converted, the references are resolved
+// against the book's headings and the items in its snippets, and
+// rewritten as plain markdown links, in the form each output format
+// needs. In plain conversion, there is no book to resolve against, and
+// references are left as they are.
+//
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::lexer::{Lexer, TokenKind};
+use crate::markdown::{self, Block};
+use crate::toc::Heading;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reference {
+    // The source line the reference is on
+    pub line: usize,
+    pub target: String,
+    pub label: Option<String>,
+}
+
+//
+// The references in one line of markdown, with where they are. Those
+// inside code spans are code, not references.
+//
+pub fn find(line: &str) -> Vec<(Range<usize>, String, Option<String>)> {
+    let mut found = Vec::new();
+    let bytes = line.as_bytes();
+    let mut i = 0;
+    while i < line.len() {
+        if bytes[i] == b'`' {
+            let run = line[i..].bytes().take_while(|&b| b == b'`').count();
+            let fence = &line[i..i + run];
+            i = match line[i + run..].find(fence) {
+                Some(end) => i + run + end + run,
+                None => i + run,
+            };
+            continue;
+        }
+        if line[i..].starts_with("[[") {
+            if let Some(end) = line[i + 2..].find("]]") {
+                let inner = &line[i + 2..i + 2 + end];
+                if !inner.trim().is_empty() && !inner.contains(['[', ']']) {
+                    let (target, label) = match inner.split_once('|') {
+                        Some((target, label)) => (target, Some(label.trim().to_string())),
+                        None => (inner, None),
+                    };
+                    found.push((i..i + 4 + end, target.trim().to_string(), label));
+                    i += 4 + end;
+                    continue;
+                }
+            }
+        }
+        i += line[i..].chars().next().map_or(1, char::len_utf8);
+    }
+    found
+}
+
+//
+// Where a reference leads: a chapter, and an anchor in it, with the
+// text the link gets unless the reference gives its own
+//
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub chapter: String,
+    pub anchor: String,
+    pub text: String,
+    // Whether the text is the name of an item, to be shown as code
+    pub code: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct Index {
+    // Each chapter's headings, in the order of the book
+    chapters: Vec<(String, Vec<Heading>)>,
+    // The items defined in each chapter's snippets, with the heading
+    // they appear under
+    items: BTreeMap<String, Vec<(String, Heading)>>,
+}
+
+impl Index {
+    pub fn add_chapter(&mut self, name: &str, blocks: &[Block], headings: &[Heading]) {
+        let mut heading: Option<&Heading> = None;
+        let mut seen = 0;
+        for block in blocks {
+            seen += count_headings(block);
+            if seen > 0 {
+                heading = headings.get(seen - 1);
+            }
+            if let (Block::Code { info, text }, Some(heading)) = (block, heading) {
+                if markdown::language(info) == Some("rust") {
+                    for item in items(text) {
+                        // The first definition in a chapter is the one linked to
+                        let places = self.items.entry(item).or_default();
+                        if !places.iter().any(|(chapter, _)| chapter == name) {
+                            places.push((name.to_string(), heading.clone()));
+                        }
+                    }
+                }
+            }
+        }
+        self.chapters.push((name.to_string(), headings.to_vec()));
+    }
+
+    //
+    // Resolves `target` for a reference made in chapter `from`. A
+    // reference that could lead to more than one place is an error.
+    //
+    pub fn resolve(&self, target: &str, from: &str) -> Result<Target, String> {
+        let (chapter, name) = match target.split_once('#') {
+            Some(("", name)) => (Some(from), name),
+            Some((chapter, name)) => (Some(chapter), name),
+            None => (None, target),
+        };
+        if let Some(chapter) = chapter {
+            let headings = self.headings(chapter).ok_or_else(|| format!("no chapter named '{}'", chapter))?;
+            if let Some(heading) = headings.iter().find(|h| h.anchor == name) {
+                return Ok(heading_target(heading));
+            }
+            return self
+                .items
+                .get(name)
+                .and_then(|places| places.iter().find(|(c, _)| c == chapter))
+                .map(|(_, heading)| item_target(name, heading))
+                .ok_or_else(|| format!("no heading or item '{}' in chapter '{}'", name, chapter));
+        }
+
+        if let Some(headings) = self.headings(name) {
+            let heading = headings.first().ok_or_else(|| format!("chapter '{}' has no headings", name))?;
+            return Ok(Target { chapter: name.to_string(), ..heading_target(heading) });
+        }
+        if let Some(heading) = self.chapters.iter().flat_map(|(_, h)| h).find(|h| h.anchor == name) {
+            return Ok(heading_target(heading));
+        }
+        match self.items.get(name).map(Vec::as_slice) {
+            Some([(_, heading)]) => Ok(item_target(name, heading)),
+            Some(places) => {
+                let chapters: Vec<&str> = places.iter().map(|(c, _)| c.as_str()).collect();
+                Err(format!("'{}' is ambiguous, it is in {}; say which with [[chapter#{}]]", name, chapters.join(", "), name))
+            }
+            None => Err(format!("nothing named '{}' in the book", name)),
+        }
+    }
+
+    fn headings(&self, chapter: &str) -> Option<&[Heading]> {
+        self.chapters.iter().find(|(name, _)| name == chapter).map(|(_, headings)| headings.as_slice())
+    }
+}
+
+fn heading_target(heading: &Heading) -> Target {
+    Target { chapter: heading.chapter.clone(), anchor: heading.anchor.clone(), text: heading.text.clone(), code: false }
+}
+
+fn item_target(name: &str, heading: &Heading) -> Target {
+    Target { text: name.to_string(), code: true, ..heading_target(heading) }
+}
+
+fn count_headings(block: &Block) -> usize {
+    match block {
+        Block::Heading { .. } => 1,
+        Block::Quote(blocks) => blocks.iter().map(count_headings).sum(),
+        Block::List { items, .. } => items.iter().flatten().map(count_headings).sum(),
+        _ => 0,
+    }
+}
+
+// The names of the functions, structs, enums and traits defined in `code`
+pub fn items(code: &str) -> Vec<String> {
+    let mut lexer = Lexer::new();
+    let mut items = Vec::new();
+    let mut keyword = false;
+    for line in code.lines() {
+        for token in lexer.line(line) {
+            let text = token.text(line);
+            match token.kind {
+                TokenKind::Whitespace => {}
+                TokenKind::Ident if keyword => {
+                    items.push(text.to_string());
+                    keyword = false;
+                }
+                TokenKind::Ident => keyword = matches!(text, "fn" | "struct" | "enum" | "trait"),
+                _ => keyword = false,
+            }
+        }
+    }
+    items
+}
+
+//
+// The markdown with its references rewritten as links, made by `link`.
+// Code blocks are left alone, and so are references that do not
+// resolve, which are reported when the chapter is converted.
+//
+pub fn rewrite(markdown: &str, index: &Index, from: &str, link: &dyn Fn(&Target) -> String) -> String {
+    let mut out = String::with_capacity(markdown.len());
+    let mut fence: Option<&str> = None;
+    for line in markdown.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        match fence {
+            Some(marker) if trimmed.starts_with(marker) => fence = None,
+            Some(_) => {}
+            None if trimmed.starts_with("```") => fence = Some("```"),
+            None if trimmed.starts_with("~~~") => fence = Some("~~~"),
+            None => {
+                let mut last = 0;
+                for (range, target, label) in find(line) {
+                    if let Ok(resolved) = index.resolve(&target, from) {
+                        out.push_str(&line[last..range.start]);
+                        let text = match (label, resolved.code) {
+                            (Some(label), _) => label,
+                            (None, true) => format!("`{}`", resolved.text),
+                            (None, false) => resolved.text.replace('[', "\\[").replace(']', "\\]"),
+                        };
+                        out.push_str(&format!("[{}]({})", text, link(&resolved)));
+                        last = range.end;
+                    }
+                }
+                out.push_str(&line[last..]);
+                continue;
+            }
+        }
+        out.push_str(line);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::toc::{self, Anchors};
+
+    fn index() -> Index {
+        let mut index = Index::default();
+        let mut anchors = Anchors::default();
+        for (name, text) in [
+            ("data_types", "# Data Types\n## Slices\n``` rust\nfn test_slices() {}\n```\n## Tests\n"),
+            ("borrowing", "# Borrowing\n## Clone\n``` rust\n#[test]\nfn test_clone() {}\nstruct Tests;\n```\n## Tests\n"),
+        ] {
+            let blocks = markdown::parse(text);
+            let headings = toc::headings(&blocks, name, &mut anchors);
+            index.add_chapter(name, &blocks, &headings);
+        }
+        index
+    }
+
+    #[test]
+    fn test_find() {
+        let found = find("See [[data_types#slices]], `[[not]]` and [[x | y]] [link](url)");
+        assert_eq!(
+            found,
+            vec![
+                (4..25, String::from("data_types#slices"), None),
+                (41..50, String::from("x"), Some(String::from("y"))),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve() {
+        let index = index();
+        let anchor = |target: &str| index.resolve(target, "borrowing").map(|t| format!("{}#{}", t.chapter, t.anchor));
+        assert_eq!(anchor("data_types#slices"), Ok(String::from("data_types#slices")));
+        assert_eq!(anchor("data_types"), Ok(String::from("data_types#data-types")));
+        assert_eq!(anchor("slices"), Ok(String::from("data_types#slices")));
+        assert_eq!(anchor("test_clone"), Ok(String::from("borrowing#clone")));
+        assert_eq!(anchor("#tests-1"), Ok(String::from("borrowing#tests-1")));
+        assert_eq!(anchor("borrowing#Tests"), Ok(String::from("borrowing#clone")));
+        assert!(anchor("data_types#clone").is_err());
+        assert!(anchor("nowhere").is_err());
+        assert!(anchor("nowhere#slices").is_err());
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let index = index();
+        let markdown = "[[slices]] and [[test_clone]] and [[borrowing|that]] and [[missing]]\n```\n[[slices]]\n```\n";
+        let link = |t: &Target| format!("{}.md#{}", t.chapter, t.anchor);
+        assert_eq!(
+            rewrite(markdown, &index, "borrowing", &link),
+            "[Slices](data_types.md#slices) and [`test_clone`](borrowing.md#clone) and \
+             [that](borrowing.md#borrowing) and [[missing]]\n```\n[[slices]]\n```\n"
+        );
+    }
+}
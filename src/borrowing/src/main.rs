@@ -26,6 +26,15 @@ mod tests {
         assert_eq!(s2, "hello world");
     }
     //
+    // Using s1 after the move does not compile:
+    //
+    // rust2md: compile-fail E0382
+    /*
+    let s1 = String::from("hello");
+    let s2 = s1;
+    assert_eq!(s1, "hello");
+    */
+    //
     // ### Variable Ownership Passed to Function
     //
     // The ownership of a variable is lost if passed
@@ -38,6 +47,7 @@ mod tests {
         check_not_empty(s1);
         /* assert_eq!(s1, "hello"); invalid operation */
     }
+    // rust2md: collapse "Helper"
     /* Ensure s1 is not empty */
     fn check_not_empty(s : String) {
         assert!(!s.is_empty());
@@ -52,6 +62,7 @@ mod tests {
         let s2 = check_not_empty_and_return(s1);
         assert_eq!(s2,"hello");
     }
+    // rust2md: collapse "Helper"
     /* Ensure s1 is not empty */
     fn check_not_empty_and_return (s : String) -> String {
         assert!(!s.is_empty());
@@ -60,7 +71,8 @@ mod tests {
     
     //
     // One other option is to pass a reference. This
-    // is what _borrowing_ means.
+    // is what _borrowing_ means. The [[data_types#slices]]
+    // seen earlier are references too.
     //
 
     #[test]
@@ -69,6 +81,7 @@ mod tests {
         check_not_empty_by_reference(&s1);
         assert_eq!(s1,"hello");
     }
+    // rust2md: collapse "Helper"
     /* Ensure s1 is not empty */
     fn check_not_empty_by_reference (s : &String) {
         assert!(!s.is_empty());
@@ -86,6 +99,7 @@ mod tests {
         append_world(&mut s1);
         assert_eq!(s1,"hello world");
     }
+    // rust2md: collapse "Helper"
     /* Mutate variable */
     fn append_world (s : &mut String) {
         s.push_str(" world");
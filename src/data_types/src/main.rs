@@ -393,11 +393,13 @@ mod tests {
     // Structs consists of one or more components using the `attribute : type` notation. 
     // The last component may or may not include a comma.
     //
+    // region: spaceship
     struct Spaceship {
         shield: bool,
         name: String,
         fuel: u8, /* Comma is optional */
     }
+    // endregion
     //
     // ## Regular Struct Construction and Query
     //
@@ -485,6 +487,7 @@ mod tests {
     //
     // A struct may be made up of other structs
     //
+    // rust2md: include spaceship
     struct SpacePort {
         name : String,
         docked_spaceship: Spaceship,
@@ -1,6 +0,0 @@
-#!/bin/sh
-#
-#  Required by Script
-#
-sudo apt-get update
-sudo apt-get install -y inotify-tools pandoc